@@ -0,0 +1,124 @@
+use std::fs;
+
+use super::error::Error;
+use super::layers;
+use super::{
+    ext, find_wrapper_collision, get_entry_by_name, get_wrapper_path, save_map, MapEntry,
+    SUBCOMMANDS,
+};
+
+// An alias has to work as a file name and fit into the map file
+fn validate_alias(alias: &str, entries: &[MapEntry]) -> Result<(), Error> {
+    if alias.is_empty()
+        || alias.starts_with('-')
+        || alias
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, ';' | ',' | '/'))
+    {
+        return Err(Error::Usage(format!("'{}' is not a valid alias", alias)));
+    }
+    if SUBCOMMANDS.contains(&alias) {
+        return Err(Error::Usage(format!("'{}' is a pxc subcommand", alias)));
+    }
+    if let Some(entry) = find_wrapper_collision(alias, "", entries) {
+        if !entry.is_called(alias) {
+            return Err(Error::Usage(format!(
+                "'{}' would have the same exported wrapper as '{}'",
+                alias, entry.name
+            )));
+        }
+    }
+    if let Some(entry) = entries.iter().find(|entry| entry.is_called(alias)) {
+        if entry.name == alias {
+            return Err(Error::Usage(format!(
+                "'{}' is already the name of a command",
+                alias
+            )));
+        }
+        return Err(Error::Usage(format!(
+            "'{}' is already an alias of '{}'",
+            alias, entry.name
+        )));
+    }
+    Ok(())
+}
+
+pub fn add_aliases(
+    entry_name: &str,
+    aliases: &[String],
+    entries: &mut [MapEntry],
+) -> Result<(), Error> {
+    let name = match get_entry_by_name(entry_name, entries) {
+        Some(entry) => {
+            layers::check_writable(entry)?;
+            entry.name.clone()
+        }
+        None => {
+            return Err(Error::NotFound(format!(
+                "item with name '{}' doesn't exist",
+                entry_name
+            )))
+        }
+    };
+
+    // Check all aliases first, so either all or none are added
+    for (i, alias) in aliases.iter().enumerate() {
+        if aliases[..i].contains(alias) {
+            return Err(Error::Usage(format!("'{}' is given twice", alias)));
+        }
+        validate_alias(alias, entries)?;
+    }
+
+    if let Some(entry) = entries.iter_mut().find(|entry| entry.name == name) {
+        entry.aliases.extend(aliases.iter().cloned());
+    }
+    save_map(entries)?;
+    for alias in aliases {
+        println!("[alias] '{}' now runs '{}'", alias, name);
+    }
+
+    // Exported commands get wrappers for the new aliases too
+    if get_wrapper_path(&name).exists() {
+        ext(&name, entries)?;
+    }
+    Ok(())
+}
+
+pub fn remove_alias(alias: &str, entries: &mut [MapEntry]) -> Result<(), Error> {
+    let entry = match entries
+        .iter_mut()
+        .find(|entry| entry.aliases.iter().any(|a| a == alias))
+    {
+        Some(entry) => entry,
+        None => return Err(Error::NotFound(format!("alias '{}' doesn't exist", alias))),
+    };
+    layers::check_writable(entry)?;
+
+    entry.aliases.retain(|a| a != alias);
+    println!("[alias] removed alias '{}' of '{}'", alias, entry.name);
+    save_map(entries)?;
+    remove_wrappers(&[alias.to_string()]);
+    Ok(())
+}
+
+// Remove the exported wrappers of aliases
+pub fn remove_wrappers(aliases: &[String]) {
+    for alias in aliases {
+        let extpath = get_wrapper_path(alias);
+        if extpath.exists() {
+            if let Err(e) = fs::remove_file(&extpath) {
+                eprintln!("[alias] failed to remove '{}': {}", extpath.display(), e);
+            }
+        }
+    }
+}
+
+pub fn list_aliases(entries: &[MapEntry]) {
+    println!("ALIAS\t\tCOMMAND");
+    println!("🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶");
+    for entry in entries {
+        for alias in entry.aliases.iter() {
+            println!("{: <16}{}", alias, entry.name);
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fs;
+
+use super::error::{self, Error};
+use super::meta::{self, Meta};
+use super::{confirm, envs, get_entry_by_name, placeholders, run};
+use super::{Config, MapEntry};
+
+// The body of a new composite command
+pub const TEMPLATE: &str =
+    "# Steps of this composite command, one stored command with its arguments per line.
+# -<name> continues with the next step when <name> fails,
+# finally: <name> always runs at the end, also after a failed step.
+";
+
+pub enum StepMode {
+    // Stop the composite when the step fails
+    Stop,
+    Continue,
+    Finally,
+}
+
+pub struct Step {
+    pub name: String,
+    pub args: Vec<String>,
+    pub mode: StepMode,
+}
+
+// A composite command has kind;composite in its metadata, its body lists the steps
+pub fn is_composite(meta: &Meta) -> bool {
+    meta.get("kind") == Some("composite")
+}
+
+// Split a step line into words, '..' and ".." group words and \ escapes a character
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => match chars.next() {
+                Some(c) => word.push(c),
+                None => return Err(format!("trailing backslash in '{}'", line)),
+            },
+            (Some(_), c) => word.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err(format!("unterminated quote in '{}'", line));
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// The steps in the order they run: the others in file order, then the finally steps
+pub fn parse_steps(content: &str) -> Result<Vec<Step>, String> {
+    let mut steps = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (mode, line) = if let Some(rest) = line.strip_prefix("finally:") {
+            (StepMode::Finally, rest)
+        } else if let Some(rest) = line.strip_prefix('-') {
+            (StepMode::Continue, rest)
+        } else {
+            (StepMode::Stop, line)
+        };
+
+        let mut words = split_args(line)?.into_iter();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Err("empty step".to_string()),
+        };
+        steps.push(Step {
+            name,
+            args: words.collect(),
+            mode,
+        });
+    }
+    // Stable, finally steps keep their order
+    steps.sort_by_key(|step| matches!(step.mode, StepMode::Finally));
+    Ok(steps)
+}
+
+fn read_content(entry: &MapEntry) -> Result<String, String> {
+    let path = entry.cmd_path();
+    fs::read_to_string(&path).map_err(|e| format!("unable to read '{}': {}", path.display(), e))
+}
+
+fn format_step(step: &Step) -> String {
+    let mut words = vec![step.name.clone()];
+    words.extend(step.args.iter().map(|arg| {
+        if arg.is_empty() || arg.contains(char::is_whitespace) {
+            format!("'{}'", arg)
+        } else {
+            arg.clone()
+        }
+    }));
+    words.join(" ")
+}
+
+// One line overview for list: test > build --release > -lint, finally: cleanup
+pub fn format_steps(entry: &MapEntry) -> String {
+    let steps = match read_content(entry).and_then(|content| parse_steps(&content)) {
+        Ok(steps) => steps,
+        Err(e) => return format!("invalid steps: {}", e),
+    };
+
+    let main: Vec<String> = steps
+        .iter()
+        .filter(|step| !matches!(step.mode, StepMode::Finally))
+        .map(|step| match step.mode {
+            StepMode::Continue => format!("-{}", format_step(step)),
+            _ => format_step(step),
+        })
+        .collect();
+    let finally: Vec<String> = steps
+        .iter()
+        .filter(|step| matches!(step.mode, StepMode::Finally))
+        .map(format_step)
+        .collect();
+
+    if finally.is_empty() {
+        main.join(" > ")
+    } else {
+        format!("{}, finally: {}", main.join(" > "), finally.join(" > "))
+    }
+}
+
+pub fn print_steps(entry: &MapEntry) {
+    let steps = match read_content(entry).and_then(|content| parse_steps(&content)) {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("[composite] '{}' has invalid steps: {}", entry.name, e);
+            return;
+        }
+    };
+
+    println!("composite command '{}':", entry.name);
+    for (i, step) in steps.iter().enumerate() {
+        match step.mode {
+            StepMode::Stop => println!("{: >3}. {}", i + 1, format_step(step)),
+            StepMode::Continue => println!(
+                "{: >3}. {} (continues on failure)",
+                i + 1,
+                format_step(step)
+            ),
+            StepMode::Finally => println!("  finally: {}", format_step(step)),
+        }
+    }
+}
+
+// Fill the {{placeholders}} of the step list from the arguments, like a script body
+fn get_steps(entry: &MapEntry, args: Vec<String>, config: &Config) -> Result<Vec<Step>, String> {
+    let content = read_content(entry)?;
+    let entry_meta = meta::read_meta(entry);
+
+    let all_placeholders = if entry_meta.get("placeholders") == Some("off") {
+        Vec::new()
+    } else {
+        placeholders::find_placeholders(&content)
+    };
+    let (values, rest) =
+        placeholders::take_placeholder_args(&all_placeholders.iter().collect::<Vec<_>>(), args);
+    if !rest.is_empty() {
+        return Err(format!(
+            "'{}' only takes placeholder arguments (--<name> <value>), got '{}'",
+            entry.name,
+            rest.join(" ")
+        ));
+    }
+
+    if all_placeholders.is_empty() {
+        return parse_steps(&content);
+    }
+
+    let command_env = envs::get_command_env(config, entry, &entry_meta)?;
+    let values = placeholders::resolve(
+        &all_placeholders,
+        values,
+        &HashMap::new(),
+        &command_env,
+        &entry_meta,
+    )?;
+    parse_steps(&placeholders::render(&content, &values))
+}
+
+// The entries of all steps, fails on unknown commands and composites that contain themselves
+fn resolve_steps<'a>(
+    entry: &MapEntry,
+    steps: &[Step],
+    entries: &'a [MapEntry],
+    stack: &[String],
+) -> Result<Vec<&'a MapEntry>, String> {
+    if stack.contains(&entry.name) {
+        let mut cycle = stack.to_vec();
+        cycle.push(entry.name.clone());
+        return Err(format!("composite cycle {}", cycle.join(" -> ")));
+    }
+
+    steps
+        .iter()
+        .map(|step| match get_entry_by_name(&step.name, entries) {
+            Some(step_entry) => Ok(step_entry),
+            None => Err(format!(
+                "step '{}' of '{}' doesn't exist",
+                step.name, entry.name
+            )),
+        })
+        .collect()
+}
+
+// Run the steps in order. Returns 0 if every step that has to succeed succeeded,
+// otherwise the exit code of the first failed step.
+pub fn run_composite(
+    entry: &MapEntry,
+    args: Vec<String>,
+    entries: &[MapEntry],
+    config: &Config,
+    stack: &mut Vec<String>,
+) -> i32 {
+    let (steps, step_entries) = match get_steps(entry, args, config)
+        .and_then(|steps| resolve_steps(entry, &steps, entries, stack).map(|e| (steps, e)))
+    {
+        Ok(steps) => steps,
+        Err(e) => return error::report("composite", &Error::Store(e)),
+    };
+
+    stack.push(entry.name.clone());
+    let mut failed: Option<(String, i32)> = None;
+    let total = steps.len();
+    for (i, (step, step_entry)) in steps.iter().zip(step_entries.iter()).enumerate() {
+        let is_finally = matches!(step.mode, StepMode::Finally);
+        if failed.is_some() && !is_finally {
+            continue;
+        }
+
+        println!(
+            "[composite] '{}' step {}/{}: {}",
+            entry.name,
+            i + 1,
+            total,
+            format_step(step)
+        );
+        let code = run::run_entry(step_entry, step.args.clone(), entries, config, stack);
+        if code == 0 {
+            continue;
+        }
+
+        match step.mode {
+            StepMode::Continue => {
+                eprintln!("[composite] step '{}' failed, continuing", step.name)
+            }
+            _ => {
+                if failed.is_none() {
+                    failed = Some((step.name.clone(), code));
+                }
+            }
+        }
+    }
+    stack.pop();
+
+    match failed {
+        Some((step, code)) => {
+            eprintln!("[composite] '{}' failed at step '{}'", entry.name, step);
+            code
+        }
+        None => {
+            println!("[composite] '{}' finished", entry.name);
+            0
+        }
+    }
+}
+
+// Describe every step like a dry run of the step itself
+pub fn dry_run_composite(
+    entry: &MapEntry,
+    args: Vec<String>,
+    entries: &[MapEntry],
+    config: &Config,
+    stack: &mut Vec<String>,
+) -> Result<(), Error> {
+    let (steps, step_entries) = get_steps(entry, args, config)
+        .and_then(|steps| resolve_steps(entry, &steps, entries, stack).map(|e| (steps, e)))
+        .map_err(Error::Store)?;
+
+    println!("{: <16}{} (composite)", "command", entry.name);
+    println!("{: <16}{}", "category", entry.category);
+    if confirm::is_protected(&meta::read_meta(entry)) {
+        println!("{: <16}asks for confirmation", "confirm");
+    }
+
+    stack.push(entry.name.clone());
+    let total = steps.len();
+    for (i, (step, step_entry)) in steps.iter().zip(step_entries.iter()).enumerate() {
+        let mode = match step.mode {
+            StepMode::Stop => "",
+            StepMode::Continue => ", continues on failure",
+            StepMode::Finally => ", finally",
+        };
+        println!();
+        println!(
+            "{} step {}/{}{}: {}",
+            stack.join(" > "),
+            i + 1,
+            total,
+            mode,
+            format_step(step)
+        );
+        run::describe_entry(step_entry, step.args.clone(), entries, config, stack)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_args_handles_quotes_and_escapes() {
+        assert_eq!(
+            split_args(r#"build --msg 'a b' "c \"d\"" e\ f ''"#).unwrap(),
+            ["build", "--msg", "a b", "c \"d\"", "e f", ""]
+        );
+        assert_eq!(split_args("  ").unwrap(), Vec::<String>::new());
+        assert!(split_args("a 'b").is_err());
+        assert!(split_args("a \\").is_err());
+    }
+
+    #[test]
+    fn parse_steps_reads_modes_and_skips_comments() {
+        let steps = parse_steps("# steps\n\ntest\n-lint --fix\n").unwrap();
+        assert_eq!(steps.len(), 2);
+        assert!(matches!(steps[0].mode, StepMode::Stop));
+        assert!(matches!(steps[1].mode, StepMode::Continue));
+        assert_eq!(steps[1].name, "lint");
+        assert_eq!(steps[1].args, ["--fix"]);
+        assert!(parse_steps("finally:").is_err());
+    }
+
+    #[test]
+    fn parse_steps_puts_finally_steps_last() {
+        let steps =
+            parse_steps("finally: cleanup\nbuild\nfinally: notify done\n-lint\ntest\n").unwrap();
+        let names: Vec<&str> = steps.iter().map(|step| step.name.as_str()).collect();
+        assert_eq!(names, ["build", "lint", "test", "cleanup", "notify"]);
+        assert!(matches!(steps[3].mode, StepMode::Finally));
+        assert_eq!(steps[4].args, ["done"]);
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::{self, IsTerminal, Write};
+
+use super::error::Error;
+use super::meta::{self, Meta};
+use super::MapEntry;
+
+const DEFAULT_WARNING: &str = "this command is marked as dangerous";
+
+// A command is protected when its metadata has confirm;<warning>, with
+// confirm_name;yes the user has to type the command name instead of y
+pub fn is_protected(meta: &Meta) -> bool {
+    meta.get("confirm").is_some()
+}
+
+// Ask before running a protected command. Returns true if the command may run.
+// Without a terminal protected commands only run with --yes (or PXC_YES=1).
+pub fn confirm_run(entry: &MapEntry, meta: &Meta, assume_yes: bool) -> bool {
+    let warning = match meta.get("confirm") {
+        Some(warning) => warning,
+        None => return true,
+    };
+
+    if assume_yes {
+        return true;
+    }
+
+    let warning = if warning.is_empty() || warning == "yes" {
+        DEFAULT_WARNING
+    } else {
+        warning
+    };
+    println!("[confirm] '{}': {}", entry.name, warning);
+
+    if !io::stdin().is_terminal() {
+        eprintln!(
+            "[confirm] not running '{}' without a terminal, pass --yes to confirm",
+            entry.name
+        );
+        return false;
+    }
+
+    let type_name = meta.get("confirm_name") == Some("yes");
+    if type_name {
+        print!("type '{}' to continue: ", entry.name);
+    } else {
+        print!("continue? [y/N]: ");
+    }
+    let _ = io::stdout().flush();
+
+    let mut input_text = String::new();
+    if io::stdin().read_line(&mut input_text).is_err() {
+        return false;
+    }
+
+    let input = input_text.trim();
+    let confirmed = if type_name {
+        input == entry.name
+    } else {
+        input == "y" || input == "Y" || input == "yes"
+    };
+
+    if !confirmed {
+        eprintln!("[confirm] aborted");
+    }
+    confirmed
+}
+
+pub fn protect(entry: &MapEntry, warning: &str, type_name: bool) -> Result<(), Error> {
+    let mut entry_meta = meta::read_meta(entry);
+    entry_meta.set("confirm", if warning.is_empty() { "yes" } else { warning });
+    if type_name {
+        entry_meta.set("confirm_name", "yes");
+    } else {
+        entry_meta.remove("confirm_name");
+    }
+    meta::save_meta(entry, &entry_meta)?;
+    println!("[protect] '{}' now asks for confirmation", entry.name);
+    Ok(())
+}
+
+pub fn unprotect(entry: &MapEntry) -> Result<(), Error> {
+    let mut entry_meta = meta::read_meta(entry);
+    entry_meta.remove("confirm");
+    entry_meta.remove("confirm_name");
+    meta::save_meta(entry, &entry_meta)?;
+    println!(
+        "[unprotect] '{}' no longer asks for confirmation",
+        entry.name
+    );
+    Ok(())
+}
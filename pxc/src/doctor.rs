@@ -0,0 +1,439 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::{composite, meta};
+use super::{
+    gen_char_sequence, get_ext_path, get_pxc_path, get_wrapper_path, parse_map_line, read_lines,
+    remove_entry, save_map, wrapper_content, MapEntry, WRAPPER_MARKER,
+};
+
+struct Doctor {
+    fix: bool,
+    assume_yes: bool,
+    found: usize,
+    fixed: usize,
+}
+
+impl Doctor {
+    // Report an issue, returns true if it should be fixed now
+    fn report(&mut self, issue: &str, fix: &str) -> bool {
+        self.found += 1;
+        println!("[doctor] {}", issue);
+        if !self.fix {
+            return false;
+        }
+        if self.assume_yes {
+            println!("[doctor] {}", fix);
+            return true;
+        }
+
+        print!("[doctor] {}? [y/N]: ", fix);
+        let _ = io::stdout().flush();
+        let mut input_text = String::new();
+        if io::stdin().read_line(&mut input_text).is_err() {
+            return false;
+        }
+        let input = input_text.trim();
+        input == "y" || input == "Y" || input == "yes"
+    }
+
+    fn done(&mut self, result: io::Result<()>) {
+        match result {
+            Ok(()) => self.fixed += 1,
+            Err(e) => eprintln!("[doctor] fix failed: {}", e),
+        }
+    }
+}
+
+fn get_cmd_path(filehash: &str) -> PathBuf {
+    Path::new(&get_pxc_path()).join("cmd").join(filehash)
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+// Lines read_map_file skips, they are dropped when the map is saved
+fn check_map_lines(doctor: &mut Doctor) -> bool {
+    let mut drop_lines = false;
+    let map_file = Path::new(&get_pxc_path()).join("map").join("pxc");
+    if let Ok(map_lines) = read_lines(map_file) {
+        for (number, line) in map_lines.map_while(Result::ok).enumerate() {
+            if line.trim().is_empty() || parse_map_line(&line).is_some() {
+                continue;
+            }
+            if doctor.report(
+                &format!("map line {} is malformed: '{}'", number + 1, line),
+                "remove the line from the map",
+            ) {
+                drop_lines = true;
+                doctor.fixed += 1;
+            }
+        }
+    }
+    drop_lines
+}
+
+// Names and aliases have to be unique
+fn check_names(doctor: &mut Doctor, entries: &mut [MapEntry]) -> bool {
+    let mut changed = false;
+    for i in 0..entries.len() {
+        if let Some(j) = (0..i).find(|&j| entries[j].is_called(&entries[i].name)) {
+            let name = entries[i].name.clone();
+            let mut n = 2;
+            while entries
+                .iter()
+                .any(|e| e.is_called(&format!("{}-{}", name, n)))
+            {
+                n += 1;
+            }
+            let new_name = format!("{}-{}", name, n);
+            if doctor.report(
+                &format!(
+                    "'{}' ({}) has the same name as '{}' ({})",
+                    name, entries[i].filehash, entries[j].name, entries[j].filehash
+                ),
+                &format!("rename the second '{}' to '{}'", name, new_name),
+            ) {
+                entries[i].name = new_name;
+                doctor.fixed += 1;
+                changed = true;
+            }
+        }
+
+        let mut aliases: Vec<String> = Vec::new();
+        for alias in entries[i].aliases.clone() {
+            let issue = match (0..i).find(|&j| entries[j].is_called(&alias)) {
+                Some(j) => Some(format!("is already used by '{}'", entries[j].name)),
+                // The own name, or twice in the list
+                None if alias == entries[i].name || aliases.contains(&alias) => {
+                    Some("is given twice".to_string())
+                }
+                None => None,
+            };
+            match issue {
+                Some(issue)
+                    if doctor.report(
+                        &format!("alias '{}' of '{}' {}", alias, entries[i].name, issue),
+                        &format!("remove the alias '{}' from '{}'", alias, entries[i].name),
+                    ) =>
+                {
+                    doctor.fixed += 1;
+                    changed = true;
+                }
+                _ => aliases.push(alias),
+            }
+        }
+        entries[i].aliases = aliases;
+    }
+    changed
+}
+
+// Every command needs a script file of its own
+fn check_filehashes(doctor: &mut Doctor, entries: &mut [MapEntry]) -> bool {
+    let mut changed = false;
+    for i in 0..entries.len() {
+        let other = match entries[..i]
+            .iter()
+            .find(|other| other.filehash == entries[i].filehash)
+        {
+            Some(other) => other.name.clone(),
+            None => continue,
+        };
+        if !doctor.report(
+            &format!(
+                "'{}' and '{}' share the script file cmd/{}",
+                other, entries[i].name, entries[i].filehash
+            ),
+            &format!("give '{}' a copy of the script", entries[i].name),
+        ) {
+            continue;
+        }
+
+        let mut filehash = gen_char_sequence();
+        while entries.iter().any(|e| e.filehash == filehash) || get_cmd_path(&filehash).exists() {
+            filehash = gen_char_sequence();
+        }
+        let old = entries[i].clone();
+        let result = fs::copy(get_cmd_path(&old.filehash), get_cmd_path(&filehash)).map(|_| ());
+        if result.is_ok() {
+            entries[i].filehash = filehash;
+            if let Err(e) = meta::save_meta(&entries[i], &meta::read_meta(&old)) {
+                eprintln!("[doctor] {}", e);
+            }
+            changed = true;
+        }
+        doctor.done(result);
+    }
+    changed
+}
+
+fn check_scripts(doctor: &mut Doctor, entries: &mut Vec<MapEntry>) -> bool {
+    let mut changed = false;
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = &entries[i];
+        let cmdpath = get_cmd_path(&entry.filehash);
+        let metadata = match fs::metadata(&cmdpath) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                if doctor.report(
+                    &format!(
+                        "'{}' points at the missing script cmd/{}",
+                        entry.name, entry.filehash
+                    ),
+                    &format!("remove '{}' from the map", entry.name),
+                ) {
+                    remove_entry(entries, i);
+                    doctor.fixed += 1;
+                    changed = true;
+                    continue;
+                }
+                i += 1;
+                continue;
+            }
+        };
+
+        // Composite commands are never executed themselves
+        let mut mode = metadata.permissions().mode() & 0o7777;
+        if mode & 0o100 == 0
+            && !composite::is_composite(&meta::read_meta(entry))
+            && doctor.report(
+                &format!(
+                    "the script of '{}' (cmd/{}) is not executable",
+                    entry.name, entry.filehash
+                ),
+                "make it executable",
+            )
+        {
+            mode |= 0o700;
+            let result = fs::set_permissions(&cmdpath, fs::Permissions::from_mode(mode));
+            doctor.done(result);
+        }
+        // Scripts may contain credentials, stores from before they were created private
+        // still have world-readable ones
+        if mode & 0o077 != 0
+            && doctor.report(
+                &format!(
+                    "the script of '{}' (cmd/{}) is accessible by other users",
+                    entry.name, entry.filehash
+                ),
+                "make it private",
+            )
+        {
+            let result = fs::set_permissions(&cmdpath, fs::Permissions::from_mode(mode & 0o700));
+            doctor.done(result);
+        }
+        i += 1;
+    }
+    changed
+}
+
+// Scripts, metadata and revisions no command refers to
+fn check_orphans(doctor: &mut Doctor, entries: &[MapEntry]) {
+    let filehashes: HashSet<&str> = entries.iter().map(|e| &e.filehash[..]).collect();
+    for (dir, what) in [
+        ("cmd", "script"),
+        ("meta", "metadata"),
+        ("rev", "revisions"),
+    ] {
+        let dir_path = Path::new(&get_pxc_path()).join(dir);
+        let mut orphans: Vec<PathBuf> = match fs::read_dir(&dir_path) {
+            Ok(files) => files
+                .map_while(Result::ok)
+                .filter(|file| !filehashes.contains(&file.file_name().to_string_lossy()[..]))
+                .map(|file| file.path())
+                .collect(),
+            Err(_) => continue,
+        };
+        orphans.sort();
+
+        for orphan in orphans {
+            let name = orphan.file_name().unwrap_or_default().to_string_lossy();
+            if doctor.report(
+                &format!("orphan {} {}/{} belongs to no command", what, dir, name),
+                &format!("delete {}/{}", dir, name),
+            ) {
+                doctor.done(remove_path(&orphan));
+            }
+        }
+    }
+}
+
+// What an exported wrapper runs, see write_wrapper
+enum WrapperTarget {
+    // exec pxc "<name>" "$@"
+    Pxc(String),
+    // exec "<store>/cmd/<filehash>" "$@"
+    Script(PathBuf),
+}
+
+// The target of a wrapper written by ext and if it has the marker of this store. None for
+// other files and wrappers marked for another store, e.g. of other users.
+fn parse_wrapper(content: &str) -> Option<(WrapperTarget, bool)> {
+    let mut lines = content.lines();
+    let mut line = lines.next()?;
+    let marked = match line.strip_prefix(WRAPPER_MARKER) {
+        Some(store) if store == get_pxc_path() => true,
+        Some(_) => return None,
+        None => false,
+    };
+    if marked {
+        line = lines.next()?;
+    }
+    if lines.next().is_some() || !content.ends_with('\n') {
+        return None;
+    }
+
+    let quoted = line.strip_suffix(" \"$@\"")?;
+    if let Some(name) = quoted
+        .strip_prefix("exec pxc \"")
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        return (!name.is_empty() && !name.contains('"'))
+            .then(|| (WrapperTarget::Pxc(name.to_string()), marked));
+    }
+    let script = Path::new(quoted.strip_prefix("exec \"")?.strip_suffix('"')?);
+    let filehash = script.file_name()?.to_str()?;
+    filehash
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric())
+        .then(|| (WrapperTarget::Script(script.to_path_buf()), marked))
+}
+
+// Exported wrappers of commands that were removed or renamed, or whose script is gone.
+// Only wrappers written by ext for this store are looked at: marked ones, and unmarked
+// ones (written before the marker) that run a command or script of this store.
+fn check_wrappers(doctor: &mut Doctor, entries: &[MapEntry], shared: &[MapEntry]) {
+    // Wrapper path -> the command it runs
+    let expected: HashMap<PathBuf, &MapEntry> = entries
+        .iter()
+        .chain(shared.iter())
+        .flat_map(|entry| {
+            std::iter::once(&entry.name)
+                .chain(entry.aliases.iter())
+                .map(move |name| (get_wrapper_path(name), entry))
+        })
+        .collect();
+    let own_script = |script: &Path| {
+        script.parent() == Some(&get_cmd_path(""))
+            || shared.iter().any(|entry| entry.cmd_path() == script)
+    };
+
+    let mut wrappers: Vec<PathBuf> = match fs::read_dir(get_ext_path()) {
+        Ok(files) => files
+            .map_while(Result::ok)
+            .map(|file| file.path())
+            .filter(|path| path.to_string_lossy().ends_with(".!"))
+            .collect(),
+        Err(_) => return,
+    };
+    wrappers.sort();
+
+    for wrapper in wrappers {
+        let target = match fs::read_to_string(&wrapper)
+            .ok()
+            .and_then(|content| parse_wrapper(&content))
+        {
+            Some((target, true)) => target,
+            Some((WrapperTarget::Pxc(name), false))
+                if entries
+                    .iter()
+                    .chain(shared.iter())
+                    .any(|entry| entry.is_called(&name)) =>
+            {
+                WrapperTarget::Pxc(name)
+            }
+            Some((WrapperTarget::Script(script), false)) if own_script(&script) => {
+                WrapperTarget::Script(script)
+            }
+            _ => continue,
+        };
+
+        // The issue and the line the wrapper should have, None if it should be deleted
+        let (issue, line) = match (target, expected.get(&wrapper)) {
+            (_, None) => ("belongs to no command".to_string(), None),
+            (WrapperTarget::Script(script), _) if !script.exists() => {
+                ("runs a missing script".to_string(), None)
+            }
+            // Commands of other layers have to go through pxc, which checks their signature
+            (WrapperTarget::Script(_), Some(entry)) if !entry.is_personal() => (
+                format!(
+                    "runs a command of layer '{}' without checking its signature",
+                    entry.layer
+                ),
+                Some(wrapper_content(&format!(
+                    "exec pxc \"{}\" \"$@\"",
+                    entry.name
+                ))),
+            ),
+            _ => continue,
+        };
+        let issue = format!("exported wrapper '{}' {}", wrapper.display(), issue);
+        match line {
+            Some(line) => {
+                if doctor.report(&issue, &format!("run '{}' through pxc", wrapper.display())) {
+                    doctor.done(fs::write(&wrapper, line));
+                }
+            }
+            None => {
+                if doctor.report(&issue, &format!("delete '{}'", wrapper.display())) {
+                    doctor.done(fs::remove_file(&wrapper));
+                }
+            }
+        }
+    }
+}
+
+// Check the personal store for inconsistencies and with fix repair them, asking for
+// each one unless assume_yes is set. Fails if issues are left.
+pub fn doctor(entries: Vec<MapEntry>, fix: bool, assume_yes: bool) -> Result<(), Error> {
+    // Other layers are read-only, only their exported wrappers are expected
+    let (mut entries, shared): (Vec<MapEntry>, Vec<MapEntry>) =
+        entries.into_iter().partition(|entry| entry.is_personal());
+    let entries = &mut entries;
+    let mut doctor = Doctor {
+        fix,
+        assume_yes,
+        found: 0,
+        fixed: 0,
+    };
+
+    let mut map_changed = check_map_lines(&mut doctor);
+    map_changed |= check_names(&mut doctor, entries);
+    map_changed |= check_filehashes(&mut doctor, entries);
+    map_changed |= check_scripts(&mut doctor, entries);
+    if map_changed {
+        save_map(entries)?;
+    }
+    check_orphans(&mut doctor, entries);
+    check_wrappers(&mut doctor, entries, &shared);
+
+    if doctor.found == 0 {
+        println!("[doctor] no issues found");
+        return Ok(());
+    }
+    if fix {
+        println!(
+            "[doctor] {} issues found, {} fixed",
+            doctor.found, doctor.fixed
+        );
+    }
+
+    match doctor.found - doctor.fixed {
+        0 => Ok(()),
+        left if fix => Err(Error::Store(format!("{} issues left", left))),
+        left => Err(Error::Store(format!(
+            "{} issues found, run 'pxc doctor --fix' to repair them",
+            left
+        ))),
+    }
+}
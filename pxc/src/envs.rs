@@ -0,0 +1,321 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::meta::Meta;
+use super::{get_pxc_path, Config, MapEntry};
+
+// A variable set for a command, source tells which level it came from
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+    pub source: String,
+}
+
+// Relative env file paths are relative to the pxc directory, ~/ to the home directory
+fn resolve_env_file(path: &str) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = home::home_dir() {
+            return home.join(rest);
+        }
+    }
+    Path::new(&get_pxc_path()).join(path)
+}
+
+// Expand $VAR, ${VAR} and ${VAR:-default}, lookup is asked for the value of VAR
+pub fn expand(value: &str, lookup: &dyn Fn(&str) -> Option<String>) -> String {
+    let mut expanded = String::new();
+    let mut chars = value.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                expanded.push('$');
+                chars.next();
+            }
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let mut inner = String::new();
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                    inner.push(c);
+                }
+                let (name, default) = match inner.split_once(":-") {
+                    Some((name, default)) => (name, Some(default)),
+                    None => (inner.as_str(), None),
+                };
+                // ${VAR:-default} also uses the default for an empty VAR, like sh
+                match (lookup(name), default) {
+                    (Some(value), Some(_)) if !value.is_empty() => expanded.push_str(&value),
+                    (_, Some(default)) => expanded.push_str(&expand(default, lookup)),
+                    (Some(value), None) => expanded.push_str(&value),
+                    (None, None) => {}
+                }
+            }
+            '$' if chars
+                .peek()
+                .is_some_and(|c| c.is_ascii_alphabetic() || *c == '_') =>
+            {
+                let mut name = String::new();
+                while let Some(c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || *c == '_') {
+                        break;
+                    }
+                    name.push(*c);
+                    chars.next();
+                }
+                expanded.push_str(&lookup(&name).unwrap_or_default());
+            }
+            _ => expanded.push(c),
+        }
+    }
+
+    expanded
+}
+
+// Parse a KEY=VALUE line of an env file, also accepts a leading "export ".
+// Returns the key, the unquoted value and whether the value should be expanded
+// (single quoted values are taken literally).
+pub fn parse_env_line(line: &str) -> Option<(String, String, bool)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+
+    let (key, value) = line.split_once('=')?;
+    let key = key.trim();
+    if key.is_empty()
+        || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        || key.starts_with(|c: char| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let value = value.trim();
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        return Some((
+            key.to_string(),
+            value[1..value.len() - 1].to_string(),
+            false,
+        ));
+    }
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let value = value[1..value.len() - 1]
+            .replace("\\n", "\n")
+            .replace("\\\"", "\"");
+        return Some((key.to_string(), value, true));
+    }
+
+    // Unquoted values end at an inline comment
+    let value = match value.find(" #") {
+        Some(pos) => value[..pos].trim_end(),
+        None => value,
+    };
+    Some((key.to_string(), value.to_string(), true))
+}
+
+fn set_var(vars: &mut Vec<EnvVar>, key: String, value: String, source: String) {
+    vars.retain(|var| var.key != key);
+    vars.push(EnvVar { key, value, source });
+}
+
+// Value of name in the command environment, falling back to the caller's environment
+pub fn lookup_var(vars: &[EnvVar], name: &str) -> Option<String> {
+    match vars.iter().find(|var| var.key == name) {
+        Some(var) => Some(var.value.clone()),
+        None => env::var(name).ok(),
+    }
+}
+
+// Apply one level: env lines (KEY=VALUE) and env files in the given order
+fn apply_level(vars: &mut Vec<EnvVar>, lines: &[(&str, &str)], source: &str) -> Result<(), String> {
+    for (kind, value) in lines {
+        match *kind {
+            "env" => match parse_env_line(value) {
+                Some((key, value, expand_value)) => {
+                    let value = if expand_value {
+                        expand(&value, &|name| lookup_var(vars, name))
+                    } else {
+                        value
+                    };
+                    set_var(vars, key, value, source.to_string());
+                }
+                None => return Err(format!("invalid env line '{}' ({})", value, source)),
+            },
+            "envfile" => {
+                let path = resolve_env_file(value);
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| format!("unable to read env file '{}': {}", path.display(), e))?;
+                let file_source = format!("{} {}", source, path.display());
+                for line in content.lines() {
+                    if let Some((key, value, expand_value)) = parse_env_line(line) {
+                        let value = if expand_value {
+                            expand(&value, &|name| lookup_var(vars, name))
+                        } else {
+                            value
+                        };
+                        set_var(vars, key, value, file_source.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The environment changes of a command: global config, then the category
+// (env.<category>/envfile.<category> config lines), then the command metadata.
+// Later levels override earlier ones and can refer to their values.
+// k8s/prod/eu -> k8s, k8s/prod, k8s/prod/eu
+fn namespace_chain(category: &str) -> Vec<&str> {
+    category
+        .match_indices('/')
+        .map(|(i, _)| &category[..i])
+        .chain(std::iter::once(category))
+        .collect()
+}
+
+pub fn get_command_env(
+    config: &Config,
+    entry: &MapEntry,
+    meta: &Meta,
+) -> Result<Vec<EnvVar>, String> {
+    let mut vars: Vec<EnvVar> = Vec::new();
+
+    let global: Vec<(&str, &str)> = config
+        .env
+        .iter()
+        .filter(|(key, _)| key == "env" || key == "envfile")
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    apply_level(&mut vars, &global, "global")?;
+
+    // Every namespace of the category from the outermost, k8s then k8s/prod
+    for category_name in namespace_chain(&entry.category) {
+        let env_key = format!("env.{}", category_name);
+        let envfile_key = format!("envfile.{}", category_name);
+        let category: Vec<(&str, &str)> = config
+            .env
+            .iter()
+            .filter(|(key, _)| *key == env_key || *key == envfile_key)
+            .map(|(key, value)| (key.split('.').next().unwrap_or(""), value.as_str()))
+            .collect();
+        apply_level(&mut vars, &category, &format!("category {}", category_name))?;
+    }
+
+    let mut command: Vec<(&str, &str)> = Vec::new();
+    for (key, value) in meta.get_entries() {
+        if key == "env" || key == "envfile" {
+            command.push((key, value));
+        }
+    }
+    apply_level(&mut vars, &command, "command")?;
+
+    Ok(vars)
+}
+
+pub fn to_pairs(vars: &[EnvVar]) -> Vec<(String, String)> {
+    vars.iter()
+        .map(|var| (var.key.clone(), var.value.clone()))
+        .collect()
+}
+
+pub fn print_env(vars: &[EnvVar]) {
+    if vars.is_empty() {
+        println!("[env] no environment changes, the command inherits the caller's environment");
+        return;
+    }
+
+    let key_width = vars
+        .iter()
+        .map(|var| var.key.len() + 1)
+        .fold(16, usize::max);
+    let value_width = vars
+        .iter()
+        .map(|var| var.value.len() + 1)
+        .fold(16, usize::max);
+
+    println!(
+        "{: <key_width$}{: <value_width$}SOURCE",
+        "VARIABLE",
+        "VALUE",
+        key_width = key_width,
+        value_width = value_width
+    );
+    println!("{}", "🭶".repeat(key_width + value_width + 6));
+    for var in vars {
+        let overrides = match env::var(&var.key) {
+            Ok(value) if value != var.value => " (overrides caller)",
+            _ => "",
+        };
+        println!(
+            "{: <key_width$}{: <value_width$}{}{}",
+            var.key,
+            var.value,
+            var.source,
+            overrides,
+            key_width = key_width,
+            value_width = value_width
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOME" => Some("/home/u".to_string()),
+            "EMPTY" => Some("".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn expand_replaces_variables_and_defaults() {
+        assert_eq!(expand("$HOME/.kube", &lookup), "/home/u/.kube");
+        assert_eq!(expand("${HOME}x", &lookup), "/home/ux");
+        assert_eq!(expand("${MISSING:-a$HOME}", &lookup), "a/home/u");
+        assert_eq!(expand("${EMPTY:-def}", &lookup), "def");
+        assert_eq!(expand("$MISSING-", &lookup), "-");
+        assert_eq!(expand("\\$HOME $1 $", &lookup), "$HOME $1 $");
+    }
+
+    #[test]
+    fn parse_env_line_handles_quotes_export_and_comments() {
+        assert_eq!(
+            parse_env_line("export A=b # note"),
+            Some(("A".to_string(), "b".to_string(), true))
+        );
+        assert_eq!(
+            parse_env_line("A='$lit # x'"),
+            Some(("A".to_string(), "$lit # x".to_string(), false))
+        );
+        assert_eq!(
+            parse_env_line("A=\"x\\ny \\\"q\\\"\""),
+            Some(("A".to_string(), "x\ny \"q\"".to_string(), true))
+        );
+        assert_eq!(
+            parse_env_line("A="),
+            Some(("A".to_string(), "".to_string(), true))
+        );
+        assert_eq!(parse_env_line("# comment"), None);
+        assert_eq!(parse_env_line("1A=b"), None);
+        assert_eq!(parse_env_line("A-B=c"), None);
+        assert_eq!(parse_env_line("novalue"), None);
+    }
+
+    #[test]
+    fn namespace_chain_goes_from_outer_to_inner() {
+        assert_eq!(
+            namespace_chain("k8s/prod/eu"),
+            ["k8s", "k8s/prod", "k8s/prod/eu"]
+        );
+        assert_eq!(namespace_chain("ops"), ["ops"]);
+    }
+}
@@ -0,0 +1,106 @@
+use std::env;
+use std::fmt;
+use std::io;
+
+// Exit codes of pxc itself, the usual ones of shells and sysexits.h. A command that
+// ran exits with the exit code of its script, or with 128 + n if the script was killed
+// by signal n, so a script can exit with the same codes.
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_USAGE: i32 = 64;
+pub const EXIT_STORE: i32 = 65;
+pub const EXIT_IO: i32 = 74;
+pub const EXIT_TIMEOUT: i32 = 124;
+pub const EXIT_EXEC: i32 = 126;
+pub const EXIT_NOT_FOUND: i32 = 127;
+
+// With PXC_EXIT_CODES=reserved pxc's own exit codes are moved to 240-246 and script
+// exit codes from 239 up are reported as 239, so the two can't be confused
+const RESERVED_CODES: [i32; 7] = [
+    EXIT_FAILURE,
+    EXIT_USAGE,
+    EXIT_STORE,
+    EXIT_IO,
+    EXIT_TIMEOUT,
+    EXIT_EXEC,
+    EXIT_NOT_FOUND,
+];
+const RESERVED_BASE: i32 = 240;
+
+fn reserved_mode() -> bool {
+    env::var("PXC_EXIT_CODES").is_ok_and(|v| v == "reserved")
+}
+
+// The exit code for one of pxc's own EXIT_* codes
+pub fn own_code(code: i32) -> i32 {
+    match RESERVED_CODES.iter().position(|other| *other == code) {
+        Some(i) if reserved_mode() => RESERVED_BASE + i as i32,
+        _ => code,
+    }
+}
+
+// The exit code for the exit code of a script
+pub fn script_code(code: i32) -> i32 {
+    if reserved_mode() && code >= RESERVED_BASE - 1 {
+        RESERVED_BASE - 1
+    } else {
+        code
+    }
+}
+
+pub enum Error {
+    // Missing or invalid arguments, for pxc or for the parameters of a command
+    Usage(String),
+    // No command, alias, template, secret or revision with this name
+    NotFound(String),
+    // A line of the map file that can't be parsed
+    Map { line: usize, text: String },
+    // The store is inconsistent or has invalid metadata, see pxc doctor
+    Store(String),
+    // Reading or writing a file of the store failed
+    Io { context: String, source: io::Error },
+    // The script could not be run
+    Exec(String),
+    // Anything else, e.g. a failed step or job
+    Failed(String),
+}
+
+impl Error {
+    pub fn io(context: String, source: io::Error) -> Error {
+        Error::Io { context, source }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        own_code(match self {
+            Error::Usage(_) => EXIT_USAGE,
+            Error::NotFound(_) => EXIT_NOT_FOUND,
+            Error::Map { .. } | Error::Store(_) => EXIT_STORE,
+            Error::Io { .. } => EXIT_IO,
+            Error::Exec(_) => EXIT_EXEC,
+            Error::Failed(_) => EXIT_FAILURE,
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Usage(message)
+            | Error::NotFound(message)
+            | Error::Store(message)
+            | Error::Exec(message)
+            | Error::Failed(message) => write!(f, "{}", message),
+            Error::Map { line, text } => write!(
+                f,
+                "map line {} is malformed: '{}', see 'pxc doctor'",
+                line, text
+            ),
+            Error::Io { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+// Print the error to stderr, returns the exit code for it
+pub fn report(tag: &str, e: &Error) -> i32 {
+    eprintln!("[{}] {}", tag, e);
+    e.exit_code()
+}
@@ -0,0 +1,135 @@
+use std::collections::HashSet;
+
+use super::error::Error;
+use super::meta::{self, Meta};
+use super::{get_entry_by_name, MapEntry};
+
+// A command in the dependency graph, needs holds indexes of other nodes
+pub struct Node {
+    pub name: String,
+    pub needs: Vec<usize>,
+}
+
+// The commands a command depends on, declared with needs;<name> in the metadata
+pub fn get_needs(meta: &Meta) -> Vec<String> {
+    meta.get_all("needs")
+        .iter()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn visit(
+    name: &str,
+    entries: &[MapEntry],
+    nodes: &mut Vec<Node>,
+    path: &mut Vec<String>,
+) -> Result<usize, Error> {
+    let entry = match get_entry_by_name(name, entries) {
+        Some(entry) => entry,
+        None => match path.last() {
+            Some(parent) => {
+                return Err(Error::NotFound(format!(
+                    "'{}' needs '{}' which doesn't exist",
+                    parent, name
+                )))
+            }
+            None => return Err(Error::NotFound(format!("command '{}' doesn't exist", name))),
+        },
+    };
+    // Needs may use aliases, the nodes always use the name
+    let name = &entry.name[..];
+
+    if let Some(start) = path.iter().position(|n| n == name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(name.to_string());
+        return Err(Error::Store(format!(
+            "dependency cycle {}",
+            cycle.join(" -> ")
+        )));
+    }
+    if let Some(index) = nodes.iter().position(|node| node.name == name) {
+        return Ok(index);
+    }
+
+    path.push(name.to_string());
+    let mut needs = Vec::new();
+    for need in get_needs(&meta::read_meta(entry)) {
+        let index = visit(&need, entries, nodes, path)?;
+        if !needs.contains(&index) {
+            needs.push(index);
+        }
+    }
+    path.pop();
+
+    nodes.push(Node {
+        name: name.to_string(),
+        needs,
+    });
+    Ok(nodes.len() - 1)
+}
+
+// All commands the target depends on, dependencies come before the commands that
+// need them and the target is last. Fails on unknown commands and cycles.
+pub fn build_graph(target: &str, entries: &[MapEntry]) -> Result<Vec<Node>, Error> {
+    let mut nodes = Vec::new();
+    visit(target, entries, &mut nodes, &mut Vec::new())?;
+    Ok(nodes)
+}
+
+fn print_tree(nodes: &[Node], index: usize, prefix: &str, printed: &mut HashSet<usize>) {
+    let needs = &nodes[index].needs;
+    for (i, need) in needs.iter().enumerate() {
+        let last = i == needs.len() - 1;
+        let seen = !printed.insert(*need);
+        println!(
+            "{}{}{}{}",
+            prefix,
+            if last { "└── " } else { "├── " },
+            nodes[*need].name,
+            if seen && !nodes[*need].needs.is_empty() {
+                " (see above)"
+            } else {
+                ""
+            }
+        );
+        if !seen {
+            let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+            print_tree(nodes, *need, &prefix, printed);
+        }
+    }
+}
+
+// A quoted DOT ID, names may contain any character
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn print_graph(target: &str, entries: &[MapEntry], dot: bool) -> Result<(), Error> {
+    let nodes = build_graph(target, entries)?;
+
+    if dot {
+        println!("digraph {} {{", dot_id(target));
+        for node in nodes.iter() {
+            if node.needs.is_empty() {
+                println!("    {};", dot_id(&node.name));
+            }
+            for need in node.needs.iter() {
+                println!(
+                    "    {} -> {};",
+                    dot_id(&node.name),
+                    dot_id(&nodes[*need].name)
+                );
+            }
+        }
+        println!("}}");
+        return Ok(());
+    }
+
+    let target_index = nodes.len() - 1;
+    println!("{}", nodes[target_index].name);
+    let mut printed = HashSet::new();
+    printed.insert(target_index);
+    print_tree(&nodes, target_index, "", &mut printed);
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::error::Error;
+use super::jobs::format_elapsed;
+use super::output::{self, Format, Value};
+use super::revisions::format_age;
+use super::{get_entry_by_name, get_pxc_path, read_lines, MapEntry};
+
+// history/history: one line per finished run, oldest first
+//   <unix time>;<filehash>;<name>;<exit code>;<duration ms>
+// The filehash keeps the runs of a command across renames.
+// history/lock: held (flock) while appending and trimming, so no parallel run loses its line
+const HISTORY_LIMIT: usize = 1000;
+
+pub struct Run {
+    pub time: u64,
+    pub filehash: String,
+    pub name: String,
+    pub code: i32,
+    pub duration_ms: u64,
+}
+
+fn get_history_path() -> PathBuf {
+    Path::new(&get_pxc_path()).join("history").join("history")
+}
+
+fn parse_run(line: &str) -> Option<Run> {
+    let parts: Vec<&str> = line.split(';').collect();
+    if parts.len() != 5 {
+        return None;
+    }
+    Some(Run {
+        time: parts[0].parse().ok()?,
+        filehash: parts[1].to_string(),
+        name: parts[2].to_string(),
+        code: parts[3].parse().ok()?,
+        duration_ms: parts[4].parse().ok()?,
+    })
+}
+
+pub fn read_history() -> Vec<Run> {
+    match read_lines(get_history_path()) {
+        Ok(lines) => lines
+            .map_while(Result::ok)
+            .filter_map(|line| parse_run(&line))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// Only the last HISTORY_LIMIT runs are kept, trimmed once the file holds twice as many
+fn trim_history(history_path: &Path) -> std::io::Result<()> {
+    let content = fs::read_to_string(history_path)?;
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.len() <= HISTORY_LIMIT * 2 {
+        return Ok(());
+    }
+    let tmp_path = history_path.with_extension("tmp");
+    let mut kept = lines[lines.len() - HISTORY_LIMIT..].join("\n");
+    kept.push('\n');
+    fs::write(&tmp_path, kept)?;
+    fs::rename(&tmp_path, history_path)
+}
+
+pub fn record(entry: &MapEntry, code: i32, duration: Duration) {
+    let history_path = get_history_path();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let line = format!(
+        "{};{};{};{};{}\n",
+        time,
+        entry.filehash,
+        entry.name,
+        code,
+        duration.as_millis()
+    );
+
+    // The lock is released when lock_file is dropped
+    let result = history_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(history_path.with_file_name("lock"))
+        })
+        .and_then(|lock_file| {
+            if unsafe { libc::flock(lock_file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&history_path)
+                .and_then(|mut file| file.write_all(line.as_bytes()))
+                .and_then(|_| trim_history(&history_path))
+        });
+    if let Err(e) = result {
+        eprintln!(
+            "[history] failed to write to file '{}': {}",
+            history_path.display(),
+            e
+        );
+    }
+}
+
+// Number of runs and the last run of every command, by filehash
+pub fn summarize(runs: &[Run]) -> HashMap<&str, (usize, &Run)> {
+    let mut summary: HashMap<&str, (usize, &Run)> = HashMap::new();
+    for run in runs {
+        let item = summary.entry(&run.filehash).or_insert((0, run));
+        item.0 += 1;
+        item.1 = run;
+    }
+    summary
+}
+
+pub fn format_time(time: u64) -> String {
+    format_age(UNIX_EPOCH + Duration::from_secs(time))
+}
+
+// The last limit runs, of one command if a name is given
+pub fn print_history(
+    name: Option<&str>,
+    limit: usize,
+    entries: &[MapEntry],
+    format: Format,
+) -> Result<(), Error> {
+    let filehash = match name {
+        Some(name) => match get_entry_by_name(name, entries) {
+            Some(entry) => Some(entry.filehash.clone()),
+            None => {
+                return Err(Error::NotFound(format!(
+                    "item with name '{}' doesn't exist",
+                    name
+                )))
+            }
+        },
+        None => None,
+    };
+
+    let runs: Vec<Run> = read_history()
+        .into_iter()
+        .filter(|run| filehash.as_ref().is_none_or(|f| *f == run.filehash))
+        .collect();
+    let runs = &runs[runs.len().saturating_sub(limit)..];
+
+    if format != Format::Table {
+        let rows: Vec<Vec<Value>> = runs
+            .iter()
+            .map(|run| {
+                vec![
+                    Value::Int(run.time as i64),
+                    Value::Str(run.name.clone()),
+                    Value::Str(run.filehash.clone()),
+                    Value::Int(run.code as i64),
+                    Value::Int(run.duration_ms as i64),
+                ]
+            })
+            .collect();
+        output::print_rows(
+            format,
+            &["time", "name", "filehash", "exit", "duration_ms"],
+            &rows,
+        );
+        return Ok(());
+    }
+
+    if runs.is_empty() {
+        println!("[history] no runs recorded yet");
+        return Ok(());
+    }
+    println!("TIME\t\tNAME\t\tEXIT\tDURATION");
+    println!("{}", "🭶".repeat(44));
+    for run in runs {
+        println!(
+            "{: <16}{: <16}{: <8}{}",
+            format_time(run.time),
+            run.name,
+            run.code,
+            format_elapsed(Duration::from_millis(run.duration_ms))
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use super::output::{self, Format, Value};
+use super::MapEntry;
+use super::{composite, confirm, history, meta, revisions, run};
+
+const FIELDS: [&str; 16] = [
+    "name",
+    "category",
+    "filehash",
+    "path",
+    "aliases",
+    "description",
+    "interpreter",
+    "composite",
+    "protected",
+    "schedule",
+    "revisions",
+    "runs",
+    "last_run",
+    "last_exit",
+    "last_duration_ms",
+    "layer",
+];
+
+// Everything pxc knows about a command
+pub fn print_info(entry: &MapEntry, format: Format) {
+    let entry_meta = meta::read_meta(entry);
+    let path = entry.cmd_path().to_string_lossy().into_owned();
+    let runs = history::read_history();
+    let summary = history::summarize(&runs);
+    let (run_count, last_run) = match summary.get(&entry.filehash[..]) {
+        Some((count, run)) => (*count, Some(run)),
+        None => (0, None),
+    };
+
+    let row = vec![
+        Value::Str(entry.name.clone()),
+        Value::Str(entry.category.clone()),
+        Value::Str(entry.filehash.clone()),
+        Value::Str(path.clone()),
+        Value::List(entry.aliases.clone()),
+        Value::opt_str(entry_meta.get("description")),
+        Value::opt_str(run::read_shebang(&path).as_deref()),
+        Value::Bool(composite::is_composite(&entry_meta)),
+        Value::Bool(confirm::is_protected(&entry_meta)),
+        Value::opt_str(entry_meta.get("schedule").filter(|s| !s.is_empty())),
+        Value::Int(revisions::get_revisions(entry).len() as i64),
+        Value::Int(run_count as i64),
+        last_run.map_or(Value::Null, |run| Value::Int(run.time as i64)),
+        last_run.map_or(Value::Null, |run| Value::Int(run.code as i64)),
+        last_run.map_or(Value::Null, |run| Value::Int(run.duration_ms as i64)),
+        Value::Str(entry.layer.clone()),
+    ];
+    if format != Format::Table {
+        output::print_object(format, &FIELDS, row);
+        return;
+    }
+
+    for (field, value) in FIELDS.iter().zip(row.iter()) {
+        let text = match (*field, value) {
+            ("last_run", Value::Int(time)) => history::format_time(*time as u64),
+            (_, Value::Str(text)) => text.clone(),
+            (_, Value::Int(number)) => number.to_string(),
+            (_, Value::Bool(flag)) => if *flag { "yes" } else { "no" }.to_string(),
+            (_, Value::List(items)) if !items.is_empty() => items.join(", "),
+            _ => "-".to_string(),
+        };
+        println!("{: <18}{}", field, text);
+    }
+}
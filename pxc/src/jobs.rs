@@ -0,0 +1,262 @@
+use std::env;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::retry::format_duration;
+use super::{confirm, get_entry_by_name, meta};
+use super::{Config, MapEntry};
+
+// A command to run, needs holds the indexes of the jobs that have to succeed first
+pub struct Job {
+    pub name: String,
+    pub args: Vec<String>,
+    pub needs: Vec<usize>,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum JobState {
+    Succeeded,
+    // The exit code, None if the command was killed or couldn't be started
+    Failed(Option<i32>),
+    // Not run because a job it needs failed, or the run stopped early
+    Skipped,
+}
+
+pub struct JobResult {
+    pub name: String,
+    pub state: JobState,
+    pub duration: Duration,
+}
+
+// Ask for all protected commands before anything runs, the jobs themselves
+// can't ask because they don't read from the terminal
+pub fn confirm_jobs(jobs: &[Job], entries: &[MapEntry], config: &Config) -> bool {
+    jobs.iter()
+        .all(|job| match get_entry_by_name(&job.name, entries) {
+            Some(entry) => confirm::confirm_run(entry, &meta::read_meta(entry), config.assume_yes),
+            None => false,
+        })
+}
+
+fn forward_output<R: Read + Send + 'static>(
+    output: R,
+    prefix: String,
+    stderr: bool,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines().map_while(Result::ok) {
+            if stderr {
+                eprintln!("{} | {}", prefix, line);
+            } else {
+                println!("{} | {}", prefix, line);
+            }
+        }
+    })
+}
+
+// Run the job as its own pxc process, so composites, timeouts and retries work as usual
+fn spawn_job(
+    index: usize,
+    job: &Job,
+    prefix: String,
+    config: &Config,
+    tx: Sender<(usize, JobState, Duration)>,
+) {
+    let mut command = match env::current_exe() {
+        Ok(exe) => Command::new(exe),
+        Err(_) => Command::new("pxc"),
+    };
+    // Protected commands were confirmed by confirm_jobs
+    command.arg("--yes");
+    if let Some(timeout) = config.timeout {
+        command.arg("--timeout").arg(format_duration(timeout));
+    }
+    if let Some(retries) = config.retries {
+        command.arg("--retries").arg(retries.to_string());
+    }
+    command
+        .arg(&job.name)
+        .args(&job.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let name = job.name.clone();
+    thread::spawn(move || {
+        let start = Instant::now();
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                eprintln!("[run] failed to start '{}': {}", name, e);
+                let _ = tx.send((index, JobState::Failed(None), start.elapsed()));
+                return;
+            }
+        };
+
+        let mut readers = Vec::new();
+        if let Some(stdout) = child.stdout.take() {
+            readers.push(forward_output(stdout, prefix.clone(), false));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            readers.push(forward_output(stderr, prefix, true));
+        }
+        for reader in readers {
+            let _ = reader.join();
+        }
+
+        let state = match child.wait() {
+            Ok(status) if status.success() => JobState::Succeeded,
+            Ok(status) => JobState::Failed(status.code()),
+            Err(_) => JobState::Failed(None),
+        };
+        let _ = tx.send((index, state, start.elapsed()));
+    });
+}
+
+// Run the jobs, at most limit at the same time, every job only after all jobs it
+// needs succeeded. The output of the jobs is prefixed with their name. After a
+// failure no new jobs are started unless keep_going is set, then only the jobs
+// depending on the failed one are skipped.
+pub fn run_jobs(jobs: &[Job], limit: usize, keep_going: bool, config: &Config) -> Vec<JobResult> {
+    let width = jobs.iter().map(|job| job.name.len()).max().unwrap_or(0);
+    let mut states: Vec<Option<JobState>> = vec![None; jobs.len()];
+    let mut started = vec![false; jobs.len()];
+    let mut durations = vec![Duration::ZERO; jobs.len()];
+    let (tx, rx) = mpsc::channel();
+    let mut running = 0;
+    let mut stop = false;
+
+    loop {
+        // Skip everything that depends on a failed or skipped job
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in 0..jobs.len() {
+                if !started[i]
+                    && jobs[i].needs.iter().any(|need| {
+                        matches!(
+                            states[*need],
+                            Some(JobState::Failed(_)) | Some(JobState::Skipped)
+                        )
+                    })
+                {
+                    started[i] = true;
+                    states[i] = Some(JobState::Skipped);
+                    changed = true;
+                }
+            }
+        }
+
+        if !stop {
+            for i in 0..jobs.len() {
+                if running >= limit.max(1) {
+                    break;
+                }
+                if !started[i]
+                    && jobs[i]
+                        .needs
+                        .iter()
+                        .all(|need| states[*need] == Some(JobState::Succeeded))
+                {
+                    started[i] = true;
+                    running += 1;
+                    let prefix = format!("{: <width$}", jobs[i].name, width = width);
+                    spawn_job(i, &jobs[i], prefix, config, tx.clone());
+                }
+            }
+        }
+
+        if running == 0 {
+            break;
+        }
+
+        let (i, state, duration) = match rx.recv() {
+            Ok(result) => result,
+            Err(_) => break,
+        };
+        running -= 1;
+        match &state {
+            JobState::Failed(code) => {
+                eprintln!(
+                    "[run] '{}' failed with exit code {} after {}",
+                    jobs[i].name,
+                    code.map(|c| c.to_string()).unwrap_or("-".to_string()),
+                    format_elapsed(duration)
+                );
+                if !keep_going {
+                    stop = true;
+                }
+            }
+            _ => println!(
+                "[run] '{}' finished after {}",
+                jobs[i].name,
+                format_elapsed(duration)
+            ),
+        }
+        states[i] = Some(state);
+        durations[i] = duration;
+    }
+
+    jobs.iter()
+        .enumerate()
+        .map(|(i, job)| JobResult {
+            name: job.name.clone(),
+            state: states[i].clone().unwrap_or(JobState::Skipped),
+            duration: durations[i],
+        })
+        .collect()
+}
+
+pub fn format_elapsed(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+pub fn print_summary(results: &[JobResult]) {
+    let width = results
+        .iter()
+        .map(|result| result.name.len() + 1)
+        .fold(16, usize::max);
+
+    println!();
+    println!(
+        "{: <width$}{: <12}{: <8}DURATION",
+        "NAME",
+        "STATUS",
+        "EXIT",
+        width = width
+    );
+    println!("{}", "🭶".repeat(width + 28));
+    for result in results {
+        let (status, exit) = match &result.state {
+            JobState::Succeeded => ("ok", "0".to_string()),
+            JobState::Failed(code) => (
+                "failed",
+                code.map(|c| c.to_string()).unwrap_or("-".to_string()),
+            ),
+            JobState::Skipped => ("skipped", "-".to_string()),
+        };
+        let duration = match result.state {
+            JobState::Skipped => "-".to_string(),
+            _ => format_elapsed(result.duration),
+        };
+        println!(
+            "{: <width$}{: <12}{: <8}{}",
+            result.name,
+            status,
+            exit,
+            duration,
+            width = width
+        );
+    }
+
+    let count = |state: fn(&JobState) -> bool| results.iter().filter(|r| state(&r.state)).count();
+    println!(
+        "[run] {} succeeded, {} failed, {} skipped",
+        count(|s| *s == JobState::Succeeded),
+        count(|s| matches!(s, JobState::Failed(_))),
+        count(|s| *s == JobState::Skipped)
+    );
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use super::error::Error;
+use super::meta::get_meta_path;
+use super::remotes;
+use super::{gen_char_sequence, get_pxc_path, read_map_file, validate_name, write_map_file};
+use super::{Config, MapEntry};
+
+// The personal store (~/.pxc) is the top layer and the only one pxc changes,
+// the layers of the config file follow in their order, then the accepted remotes
+pub const PERSONAL: &str = "personal";
+
+pub struct Layer {
+    pub name: String,
+    pub path: String,
+}
+
+// A command hidden by one of the same name in a layer above it
+pub struct Shadowed {
+    pub name: String,
+    pub layer: String,
+    pub by_layer: String,
+}
+
+pub fn get_layers(config: &Config) -> Vec<Layer> {
+    let mut layers = vec![Layer {
+        name: PERSONAL.to_string(),
+        path: get_pxc_path(),
+    }];
+    for (name, path) in config.layers.iter() {
+        layers.push(Layer {
+            name: name.to_string(),
+            path: path.to_string(),
+        });
+    }
+    layers.extend(remotes::get_layers());
+    layers
+}
+
+// The commands of all layers by precedence, a command of a lower layer is hidden by
+// a command or alias of the same name above it. Unavailable layers are skipped, with a
+// warning if warn is set.
+fn merge(config: &Config, warn: bool) -> Result<(Vec<MapEntry>, Vec<Shadowed>), Error> {
+    let mut entries: Vec<MapEntry> = Vec::new();
+    let mut shadowed: Vec<Shadowed> = Vec::new();
+    for layer in get_layers(config) {
+        let layer_entries = match read_map_file(&layer.path, &layer.name) {
+            Ok(layer_entries) => layer_entries,
+            Err(e) if layer.name == PERSONAL => return Err(e),
+            // A shared store that isn't mounted doesn't stop the personal commands
+            Err(e) => {
+                if warn {
+                    eprintln!("[layers] skipping layer '{}': {}", layer.name, e);
+                }
+                continue;
+            }
+        };
+
+        let above = entries.len();
+        for mut entry in layer_entries {
+            if let Some(other) = entries[..above]
+                .iter()
+                .find(|other| other.is_called(&entry.name))
+            {
+                shadowed.push(Shadowed {
+                    name: entry.name,
+                    layer: layer.name.clone(),
+                    by_layer: other.layer.clone(),
+                });
+                continue;
+            }
+            entry
+                .aliases
+                .retain(|alias| !entries[..above].iter().any(|other| other.is_called(alias)));
+            entries.push(entry);
+        }
+    }
+    Ok((entries, shadowed))
+}
+
+pub fn read_entries(config: &Config) -> Result<Vec<MapEntry>, Error> {
+    merge(config, true).map(|(entries, _)| entries)
+}
+
+pub fn warn_shadowed(config: &Config) {
+    let shadowed = merge(config, false)
+        .map(|(_, shadowed)| shadowed)
+        .unwrap_or_default();
+    for shadowed in shadowed {
+        eprintln!(
+            "[layers] '{}' of layer '{}' is shadowed by layer '{}'",
+            shadowed.name, shadowed.layer, shadowed.by_layer
+        );
+    }
+}
+
+// Commands of shared layers are read-only, they change by promoting a personal command
+pub fn check_writable(entry: &MapEntry) -> Result<(), Error> {
+    if entry.is_personal() {
+        return Ok(());
+    }
+    Err(Error::Usage(format!(
+        "'{}' belongs to the read-only layer '{}', add a personal '{}' to override it",
+        entry.name, entry.layer, entry.name
+    )))
+}
+
+// Copy a personal command with its metadata into the store of another layer,
+// replacing the command of the same name there
+pub fn promote(name: &str, to: &str, entries: &[MapEntry], config: &Config) -> Result<(), Error> {
+    let entry = entries
+        .iter()
+        .find(|entry| entry.is_called(name))
+        .ok_or_else(|| Error::NotFound(format!("item with name '{}' doesn't exist", name)))?;
+    if !entry.is_personal() {
+        return Err(Error::Usage(format!(
+            "'{}' belongs to layer '{}', only personal commands can be promoted",
+            entry.name, entry.layer
+        )));
+    }
+    // Commands added before names were checked may have a reserved or colliding name
+    validate_name(&entry.name, entries)?;
+    let layer = get_layers(config)
+        .into_iter()
+        .find(|layer| layer.name == to && layer.name != PERSONAL)
+        .ok_or_else(|| Error::NotFound(format!("layer '{}' isn't configured", to)))?;
+
+    let store = Path::new(&layer.path);
+    for dir in ["map", "cmd", "meta"] {
+        fs::create_dir_all(store.join(dir)).map_err(|e| {
+            Error::io(
+                format!("unable to create '{}'", store.join(dir).display()),
+                e,
+            )
+        })?;
+    }
+    let map_file = store.join("map").join("pxc");
+    let mut layer_entries = if map_file.exists() {
+        read_map_file(&layer.path, &layer.name)?
+    } else {
+        Vec::new()
+    };
+
+    // The filehash stays the same where possible, so the run history carries over
+    let filehash = match layer_entries
+        .iter()
+        .position(|other| other.name == entry.name)
+    {
+        Some(pos) => layer_entries.remove(pos).filehash,
+        None => {
+            let mut filehash = entry.filehash.clone();
+            while layer_entries.iter().any(|other| other.filehash == filehash)
+                || store.join("cmd").join(&filehash).exists()
+            {
+                filehash = gen_char_sequence();
+            }
+            filehash
+        }
+    };
+
+    let mut promoted = entry.clone();
+    promoted.filehash = filehash;
+    promoted.layer = layer.name.clone();
+    promoted.store = layer.path.clone();
+    promoted
+        .aliases
+        .retain(|alias| !layer_entries.iter().any(|other| other.is_called(alias)));
+
+    // Shared scripts have to be readable by everyone using the layer
+    let cmd_path = promoted.cmd_path();
+    fs::copy(entry.cmd_path(), &cmd_path)
+        .and_then(|_| fs::set_permissions(&cmd_path, fs::Permissions::from_mode(0o755)))
+        .map_err(|e| Error::io(format!("unable to write '{}'", cmd_path.display()), e))?;
+    let meta_path = get_meta_path(&promoted);
+    let meta_result = if get_meta_path(entry).exists() {
+        fs::copy(get_meta_path(entry), &meta_path).map(|_| ())
+    } else if meta_path.exists() {
+        fs::remove_file(&meta_path)
+    } else {
+        Ok(())
+    };
+    meta_result.map_err(|e| Error::io(format!("unable to write '{}'", meta_path.display()), e))?;
+
+    layer_entries.push(promoted);
+    write_map_file(&map_file, layer_entries.iter()).map_err(|e| {
+        Error::io(
+            format!("unable to write the map file '{}'", map_file.display()),
+            e,
+        )
+    })?;
+
+    println!(
+        "[promote] copied '{}' to layer '{}' ({})",
+        entry.name, layer.name, layer.path
+    );
+    println!(
+        "[promote] the personal '{}' shadows it until you remove it with 'pxc rm {}'",
+        entry.name, entry.name
+    );
+    Ok(())
+}
@@ -0,0 +1,336 @@
+use std::env;
+use std::io::{self, IsTerminal};
+
+use super::history::{self, Run};
+use super::meta::{self, Meta};
+use super::output::{self, Format, Value};
+use super::MapEntry;
+use super::{composite, matches_pattern, namespaces, run};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortKey {
+    // Grouped by category, by name within a category
+    Category,
+    Name,
+    // Last run first, never run commands last
+    Recent,
+    // Most runs first
+    Runs,
+}
+
+pub struct ListOptions {
+    // A category, or a namespace with a trailing slash
+    pub filter: String,
+    pub sort: SortKey,
+    pub long: bool,
+    pub color: bool,
+    pub pattern: Option<String>,
+    pub interpreter: Option<String>,
+    pub format: Format,
+    // Show the LAYER column, set when shared layers are configured
+    pub layered: bool,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> Option<SortKey> {
+        match key {
+            "category" => Some(SortKey::Category),
+            "name" => Some(SortKey::Name),
+            "recent" => Some(SortKey::Recent),
+            "runs" => Some(SortKey::Runs),
+            _ => None,
+        }
+    }
+}
+
+// --color auto|always|never, auto colors a terminal unless NO_COLOR is set
+pub fn use_color(mode: &str) -> Option<bool> {
+    match mode {
+        "auto" => Some(io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none()),
+        "always" => Some(true),
+        "never" => Some(false),
+        _ => None,
+    }
+}
+
+struct Row<'a> {
+    entry: &'a MapEntry,
+    meta: Meta,
+    shebang: Option<String>,
+    runs: usize,
+    last_run: Option<&'a Run>,
+}
+
+// The program a shebang runs, /usr/bin/env python3 -> python3, sh without a shebang
+pub fn interpreter_name(shebang: Option<&str>) -> String {
+    let shebang = match shebang {
+        Some(shebang) => shebang,
+        None => return "sh".to_string(),
+    };
+    let mut words = shebang.split_whitespace();
+    let mut program = words.next().unwrap_or("");
+    if program.rsplit('/').next() == Some("env") {
+        program = words.find(|word| !word.starts_with('-')).unwrap_or("");
+    }
+    program.rsplit('/').next().unwrap_or("").to_string()
+}
+
+pub fn list_categories(entries: &[MapEntry], format: Format) {
+    if format != Format::Table {
+        let rows: Vec<Vec<Value>> = namespaces::category_counts(entries)
+            .into_iter()
+            .map(|(category, count)| vec![Value::Str(category), Value::Int(count as i64)])
+            .collect();
+        output::print_rows(format, &["category", "commands"], &rows);
+        return;
+    }
+
+    println!("CATEGORIES");
+    println!("{}", "🭶".repeat(22));
+    namespaces::print_category_tree(entries);
+}
+
+// Width of the terminal on stdout, None if stdout isn't one
+fn terminal_width() -> Option<usize> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) } == 0
+        && size.ws_col > 0
+    {
+        return Some(size.ws_col as usize);
+    }
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+}
+
+fn format_last_run(run: Option<&Run>) -> String {
+    match run {
+        Some(run) if run.code == 0 => format!("ok {}", history::format_time(run.time)),
+        Some(run) => format!("exit {} {}", run.code, history::format_time(run.time)),
+        None => "-".to_string(),
+    }
+}
+
+// Cut text to width characters, marking the cut with …
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+fn paint(text: &str, code: &str, color: bool) -> String {
+    if color && !code.is_empty() {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+fn print_table(groups: &[Vec<Row>], options: &ListOptions, grouped: bool) {
+    let layered = options.layered;
+    let mut headers = vec!["NAME", "CATEGORY", "FILE", "ALIASES"];
+    if layered {
+        headers.insert(2, "LAYER");
+    }
+    if options.long {
+        headers.extend(["LAST RUN", "DESCRIPTION"]);
+    }
+
+    let cells = |row: &Row| -> Vec<String> {
+        let mut cells = vec![
+            row.entry.name.clone(),
+            row.entry.category.clone(),
+            row.entry.filehash.clone(),
+            row.entry.aliases.join(", "),
+        ];
+        if layered {
+            cells.insert(2, row.entry.layer.clone());
+        }
+        if options.long {
+            cells.push(format_last_run(row.last_run));
+            cells.push(row.meta.get("description").unwrap_or("").to_string());
+        }
+        cells
+    };
+
+    // Every column but the last is as wide as its longest cell, the last one is cut
+    // at the edge of the terminal
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len() + 2).collect();
+    for row in groups.iter().flatten() {
+        for (i, cell) in cells(row).iter().enumerate() {
+            widths[i] = widths[i].max(cell.chars().count() + 2);
+        }
+    }
+    let last = headers.len() - 1;
+    let fixed: usize = widths[..last].iter().sum();
+    let total = match terminal_width() {
+        Some(columns) => {
+            widths[last] = widths[last].min(columns.saturating_sub(fixed).max(8));
+            (fixed + widths[last]).min(columns)
+        }
+        None => fixed + widths[last],
+    };
+
+    let header: Vec<String> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| format!("{: <width$}", header, width = widths[i]))
+        .collect();
+    println!("{}", header.concat().trim_end());
+    println!("{}", "🭶".repeat(total));
+
+    for group in groups {
+        for row in group {
+            let mut line = String::new();
+            for (i, cell) in cells(row).iter().enumerate() {
+                let cell = if i == last {
+                    truncate(cell, widths[i] - 2)
+                } else {
+                    format!("{: <width$}", cell, width = widths[i])
+                };
+                let code = match i {
+                    0 => "1",
+                    _ if i == last - 1 && options.long => match row.last_run {
+                        Some(run) if run.code == 0 => "32",
+                        Some(_) => "31",
+                        None => "",
+                    },
+                    _ => "",
+                };
+                line.push_str(&paint(&cell, code, options.color));
+            }
+            println!("{}", line.trim_end());
+            if composite::is_composite(&row.meta) {
+                println!(
+                    "{: <width$}steps: {}",
+                    "",
+                    composite::format_steps(row.entry),
+                    width = widths[0]
+                );
+            }
+        }
+        // Only print a newline between categories if we're listing multiple categories
+        if grouped {
+            println!();
+        }
+    }
+}
+
+pub fn list(entries: &[MapEntry], options: &ListOptions) {
+    // A trailing slash lists a namespace: all commands whose name or category is in it
+    let category_name = &options.filter[..];
+    let namespace = category_name.strip_suffix('/');
+    let in_listing = |entry: &MapEntry| match namespace {
+        Some(namespace) => {
+            namespaces::in_namespace(&entry.name, namespace)
+                || namespaces::in_namespace(&entry.category, namespace)
+        }
+        None => category_name.is_empty() || entry.category == category_name,
+    };
+
+    let runs = history::read_history();
+    let summary = history::summarize(&runs);
+    let mut rows: Vec<Row> = entries
+        .iter()
+        .filter(|entry| in_listing(entry))
+        .filter(|entry| {
+            options
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| matches_pattern(pattern, &entry.name))
+        })
+        .map(|entry| {
+            let script_path = entry.cmd_path();
+            let (runs, last_run) = match summary.get(&entry.filehash[..]) {
+                Some((count, run)) => (*count, Some(*run)),
+                None => (0, None),
+            };
+            Row {
+                entry,
+                meta: meta::read_meta(entry),
+                shebang: run::read_shebang(&script_path.to_string_lossy()),
+                runs,
+                last_run,
+            }
+        })
+        .filter(|row| {
+            options.interpreter.as_ref().is_none_or(|interpreter| {
+                interpreter_name(row.shebang.as_deref()).starts_with(&interpreter[..])
+            })
+        })
+        .collect();
+
+    rows.sort_by(|a, b| {
+        let order = match options.sort {
+            SortKey::Category => a.entry.category.cmp(&b.entry.category),
+            SortKey::Name => std::cmp::Ordering::Equal,
+            SortKey::Recent => b.last_run.map(|r| r.time).cmp(&a.last_run.map(|r| r.time)),
+            SortKey::Runs => b.runs.cmp(&a.runs),
+        };
+        order.then(a.entry.name.cmp(&b.entry.name))
+    });
+
+    if options.format != Format::Table {
+        let rows: Vec<Vec<Value>> = rows
+            .iter()
+            .map(|row| {
+                vec![
+                    Value::Str(row.entry.name.clone()),
+                    Value::Str(row.entry.category.clone()),
+                    Value::Str(row.entry.filehash.clone()),
+                    Value::List(row.entry.aliases.clone()),
+                    Value::opt_str(row.meta.get("description")),
+                    Value::Bool(composite::is_composite(&row.meta)),
+                    Value::opt_str(row.shebang.as_deref()),
+                    Value::Int(row.runs as i64),
+                    row.last_run
+                        .map_or(Value::Null, |run| Value::Int(run.time as i64)),
+                    row.last_run
+                        .map_or(Value::Null, |run| Value::Int(run.code as i64)),
+                    Value::Str(row.entry.layer.clone()),
+                ]
+            })
+            .collect();
+        output::print_rows(
+            options.format,
+            &[
+                "name",
+                "category",
+                "filehash",
+                "aliases",
+                "description",
+                "composite",
+                "interpreter",
+                "runs",
+                "last_run",
+                "last_exit",
+                "layer",
+            ],
+            &rows,
+        );
+        return;
+    }
+
+    // Listing all categories (or a namespace) by category groups them
+    let grouped =
+        options.sort == SortKey::Category && (category_name.is_empty() || namespace.is_some());
+    let mut groups: Vec<Vec<Row>> = Vec::new();
+    if grouped {
+        // The rows are sorted by category already
+        for row in rows {
+            match groups.last_mut() {
+                Some(group) if group[0].entry.category == row.entry.category => group.push(row),
+                _ => groups.push(vec![row]),
+            }
+        }
+    } else {
+        groups.push(rows);
+    }
+    print_table(&groups, options, grouped);
+}
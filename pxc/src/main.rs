@@ -1,54 +1,262 @@
+extern crate argon2;
+extern crate chacha20poly1305;
+extern crate ed25519_dalek;
+extern crate inotify;
+extern crate libc;
 extern crate ncurses;
 extern crate rand;
+extern crate regex;
+extern crate rpassword;
+extern crate sha2;
+extern crate similar;
+extern crate ureq;
+
+mod aliases;
+mod composite;
+mod confirm;
+mod doctor;
+mod envs;
+mod error;
+mod graph;
+mod history;
+mod info;
+mod jobs;
+mod layers;
+mod listing;
+mod meta;
+mod namespaces;
+mod output;
+mod params;
+mod placeholders;
+mod remotes;
+mod retry;
+mod revisions;
+mod run;
+mod schedule;
+mod search;
+mod secrets;
+mod signing;
+mod templates;
+mod watch;
+mod workdir;
+
+use error::Error;
 use ncurses::*;
 use rand::Rng;
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::env;
 use std::fs::File;
 use std::fs::Permissions;
 use std::fs::{self};
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::ops::Deref;
 use std::os::unix::fs::PermissionsExt;
-use std::os::unix::process::CommandExt;
-use std::path::Path;
-use std::process::ExitStatus;
-use std::process::{Command, Stdio};
-use std::ptr::null;
-use std::ptr::null_mut;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::thread;
+use std::time::Duration;
 
 #[derive(Clone)]
 struct MapEntry {
     name: String,
     category: String,
     filehash: String,
+    aliases: Vec<String>,
+    // Store layer the command comes from and the root directory of its store
+    layer: String,
+    store: String,
+}
+
+impl MapEntry {
+    // A command of the personal store
+    fn new(name: String, category: String, filehash: String) -> MapEntry {
+        MapEntry {
+            name,
+            category,
+            filehash,
+            aliases: Vec::new(),
+            layer: layers::PERSONAL.to_string(),
+            store: get_pxc_path(),
+        }
+    }
+
+    fn cmd_path(&self) -> PathBuf {
+        Path::new(&self.store).join("cmd").join(&self.filehash)
+    }
+
+    fn is_personal(&self) -> bool {
+        self.layer == layers::PERSONAL
+    }
+
+    // True for the name and every alias of the command
+    fn is_called(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
 }
 
 struct Config {
     editor: String,
+    revisions: usize,
+    author: String,
+    template: String,
+    category_templates: HashMap<String, String>,
+    // env/envfile lines, also env.<category>/envfile.<category>, in file order
+    env: Vec<(String, String)>,
+    secrets_keyfile: String,
+    // Set by --yes or PXC_YES=1, not read from the config file
+    assume_yes: bool,
+    // Set by --timeout and --retries, override the command metadata
+    timeout: Option<Duration>,
+    retries: Option<u32>,
+    // Parallel jobs of pxc run
+    jobs: usize,
+    // Colors of ls: auto, always or never
+    color: String,
+    // layer;<name>;<path> lines, store layers below the personal one by precedence
+    layers: Vec<(String, String)>,
+    // Commands of shared layers without a trusted signature: off, warn or refuse,
+    // verify.<layer> lines override it per layer
+    verify: String,
+    layer_verify: HashMap<String, String>,
 }
 
+// First line of exported wrappers, followed by the pxc directory they belong to
+const WRAPPER_MARKER: &str = "# pxc wrapper of ";
+
+// Subcommands, no command or alias can be called like one of them
+const SUBCOMMANDS: &[&str] = &[
+    "h",
+    "help",
+    "print",
+    "add",
+    "edit",
+    "ext",
+    "external",
+    "rm",
+    "remove",
+    "ls",
+    "list",
+    "lsc",
+    "meta",
+    "env",
+    "protect",
+    "unprotect",
+    "secret",
+    "templates",
+    "template",
+    "revisions",
+    "revs",
+    "diff",
+    "revert",
+    "interactive",
+    "int",
+    "run",
+    "schedule",
+    "watch",
+    "alias",
+    "graph",
+    "dry",
+    "grep",
+    "search",
+    "doctor",
+    "info",
+    "history",
+    "promote",
+    "remote",
+    "sign",
+    "trust",
+    "verify",
+];
+
 fn help() {
     println!("pxc help:");
-    println!("");
-    println!("lsc                    -> List all categories.");
+    println!();
+    println!("lsc                    -> Show all categories as a tree.");
     println!("(ls | list)            -> List all commands.");
     println!("(ls | list) <name>     -> List all commands in category <name>.");
+    println!("(ls | list) <ns>/      -> List all commands named or categorized below <ns>.");
+    println!("(ls | list) [-l] [--sort name|category|recent|runs] [--match <pattern>]");
+    println!("            [--interpreter <i>] [--color auto|always|never]");
+    println!("                       -> List with description and last run, sorted, filtered.");
     println!("edit <name>            -> Edit the command <name>.");
     println!("add <name>             -> Add a new command with the name <name>.");
+    println!("add <name> [category] --template <t>");
+    println!("                       -> Add a new command created from the template <t>.");
+    println!("add <name> [category] --composite");
+    println!("                       -> Add a command that runs a sequence of other commands.");
+    println!("meta <name>            -> Edit the metadata of the command <name>.");
+    println!("meta <name> <key> [value]");
+    println!("                       -> Print or set the metadata value <key>.");
+    println!("<name> --help          -> Show the declared parameters of the command <name>.");
+    println!("env <name>             -> Show the environment changes of the command <name>.");
+    println!("protect <name> [warning] [--type-name]");
+    println!("                       -> Ask for confirmation before running the command <name>.");
+    println!("unprotect <name>       -> Run the command <name> without confirmation again.");
+    println!("--yes <name> [args]    -> Run a protected command without asking (or PXC_YES=1).");
+    println!("run [-j <n>] [-k] <name> [args]");
+    println!("                       -> Run the command <name> after the commands it needs.");
+    println!("run [-p | -j <n>] (--category <c> | --match <pattern>) [-- args]");
+    println!("                       -> Run all commands of category <c> or matching <pattern>.");
+    println!("graph <name> [--dot]   -> Show the dependencies of the command <name>.");
+    println!("watch <name> [--path <glob>]... [--debounce <ms>] [-- args]");
+    println!("                       -> Run the command <name> again whenever a file changes.");
+    println!("schedule <name> <cron> -> Run the command <name> at the cron schedule <cron>.");
+    println!("schedule (ls | rm <name>)");
+    println!("                       -> List all schedules / remove the schedule of <name>.");
+    println!("schedule sync [--target crontab|systemd-user]");
+    println!("                       -> Install the schedules as crontab lines or user timers.");
+    println!("--timeout <t> <name>   -> Stop the command <name> after <t> (e.g. 30s, 5m).");
+    println!("--retries <n> <name>   -> Retry the command <name> up to <n> times on failure.");
+    println!("<name> --dry-run [args]-> Show what running the command <name> would do.");
+    println!("dry <name> [args]      -> Same as <name> --dry-run.");
+    println!("secret set <name>      -> Store a secret, read from the terminal or stdin.");
+    println!("secret get <name>      -> Print the secret <name>.");
+    println!("secret (ls | rm <name>)-> List all secrets / remove the secret <name>.");
+    println!("grep [-i] [-C <n>] <regex>");
+    println!("                       -> Show the script lines matching <regex> with context.");
+    println!("search <words>         -> Find commands by their script, description and metadata.");
+    println!("search --reindex       -> Rebuild the search index.");
+    println!("doctor [--fix]         -> Check the store for inconsistencies (and repair them).");
+    println!("promote <name> --to <layer>");
+    println!("                       -> Copy the personal command <name> into a shared layer.");
+    println!("remote add <name> <url>-> Subscribe to the command catalog at <url>.");
+    println!("remote update [name]   -> Fetch the catalogs into the local cache.");
+    println!("remote diff [name]     -> Show what changed in the fetched catalogs.");
+    println!("remote accept [name]   -> Use the fetched catalogs' commands.");
+    println!("remote (ls | rm <name>)-> List all remotes / remove the remote <name>.");
+    println!("sign <name>            -> Sign the command <name> with your key.");
+    println!("trust add <name> <key> -> Trust the signatures of the public key <key>.");
+    println!("trust (ls | rm <name>) -> List all trusted keys / remove the key <name>.");
+    println!("verify [name]          -> Check the signatures of all commands or of <name>.");
+    println!("info <name>            -> Show everything pxc knows about the command <name>.");
+    println!("history [name] [-n <n>]-> Show the last <n> runs (default 20) of all commands or of <name>.");
+    println!("templates              -> List all templates.");
+    println!("template <t>           -> Edit (or create) the template <t>.");
     println!("print <name>           -> Print the content of the command <name>.");
     println!("ext | external         -> Export the command <name>.");
     println!("rm | remove            -> Remove the command <name>.");
+    println!("alias add <name> <alias>...");
+    println!("                       -> Make the command <name> callable as <alias>.");
+    println!("alias (ls | rm <alias>)-> List all aliases / remove the alias <alias>.");
+    println!("revisions <name>       -> List the stored revisions of the command <name>.");
+    println!("diff <name> [rev]      -> Show the changes since revision [rev] (default: latest).");
+    println!("revert <name> <rev>    -> Restore the command <name> to revision <rev>.");
+    println!();
+    println!("Exit codes: the script's own, 64 usage, 65 store, 74 I/O, 124 timeout,");
+    println!(
+        "126 not executable, 127 not found; 240-246 with PXC_EXIT_CODES=reserved (see README)."
+    );
+    println!();
+    println!("ls, lsc, info, history and search print JSON with --json, TSV with --format tsv.");
 }
 
 fn gen_char_sequence() -> String {
     const CHARSET: &[u8] = b"ABCDEF0123456789";
-    return (0..8)
+    (0..8)
         .map(|_| {
             let idx = rand::thread_rng().gen_range(0, CHARSET.len());
             CHARSET[idx] as char
         })
-        .collect();
+        .collect()
 }
 
 fn check_sequence_exists(sequence: &str, entries: &mut Vec<MapEntry>) -> bool {
@@ -57,357 +265,1114 @@ fn check_sequence_exists(sequence: &str, entries: &mut Vec<MapEntry>) -> bool {
             return true;
         }
     }
-    return false;
+    false
 }
 
 fn main() {
-    let mut entries: Vec<MapEntry> = read_map_file();
-    let config = read_config();
+    let mut config = read_config();
     let mut args = env::args().skip(1);
 
-    if let Some(arg) = args.next() {
+    let arg = match parse_global_flags(&mut args, &mut config) {
+        Ok(Some(arg)) => arg,
+        Ok(None) => {
+            help();
+            return;
+        }
+        Err(e) => process::exit(error::report("pxc", &e)),
+    };
+
+    // Errors are reported with the subcommand, errors of running a command with run
+    let tag = if SUBCOMMANDS.contains(&&arg[..]) {
+        arg.clone()
+    } else {
+        "run".to_string()
+    };
+    match dispatch(arg, args, config) {
+        Ok(code) => process::exit(code),
+        Err(e) => process::exit(error::report(&tag, &e)),
+    }
+}
+
+// Global flags before the subcommand or command name, returns the first other argument
+fn parse_global_flags(
+    args: &mut std::iter::Skip<env::Args>,
+    config: &mut Config,
+) -> Result<Option<String>, Error> {
+    while let Some(arg) = args.next() {
         match &arg[..] {
-            "h" | "help" | "--help" => help(),
-            "print" => {
-                let entry_name: String;
-                if let Some(arg1) = args.next() {
-                    entry_name = arg1;
-                } else {
-                    println!("[print] no name supplied, exiting.");
-                    return;
-                }
-                if !check_entry_exists(&entry_name, &entries) {
-                    println!("[print] item with name '{}' doesn't exist", entry_name);
-                    return;
-                }
-                print_cmd(&entry_name, entries);
+            "--yes" | "-y" => config.assume_yes = true,
+            "--timeout" => match args.next().map(|t| retry::parse_duration(&t)) {
+                Some(Ok(timeout)) => config.timeout = Some(timeout),
+                Some(Err(e)) => return Err(Error::Usage(e)),
+                None => return Err(Error::Usage("missing duration".to_string())),
+            },
+            "--retries" => match args.next().map(|r| r.parse::<u32>()) {
+                Some(Ok(retries)) => config.retries = Some(retries),
+                _ => return Err(Error::Usage("expected the number of retries".to_string())),
+            },
+            _ => return Ok(Some(arg)),
+        }
+    }
+    Ok(None)
+}
+
+// Run the subcommand arg (or the command named arg), returns the exit code
+fn dispatch(
+    arg: String,
+    mut args: std::iter::Skip<env::Args>,
+    config: Config,
+) -> Result<i32, Error> {
+    if matches!(&arg[..], "h" | "help" | "--help") {
+        help();
+        return Ok(0);
+    }
+    let mut entries: Vec<MapEntry> = layers::read_entries(&config)?;
+
+    match &arg[..] {
+        "print" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
             }
+            if !check_entry_exists(&entry_name, &entries) {
+                return Err(Error::NotFound(format!(
+                    "item with name '{}' doesn't exist",
+                    entry_name
+                )));
+            }
+            print_cmd(&entry_name, &entries)?;
+        }
 
-            "add" => {
-                let entry_name: String;
-                if let Some(arg1) = args.next() {
-                    entry_name = arg1;
-                } else {
-                    println!("[add] no name supplied, exiting.");
-                    return;
+        "add" => {
+            let mut template_name: Option<String> = None;
+            let mut is_composite = false;
+            let mut positional: Vec<String> = Vec::new();
+            while let Some(arg1) = args.next() {
+                match &arg1[..] {
+                    "--composite" => is_composite = true,
+                    "-t" | "--template" => match args.next() {
+                        Some(arg2) => template_name = Some(arg2),
+                        None => {
+                            return Err(Error::Usage("no template supplied".to_string()));
+                        }
+                    },
+                    _ => positional.push(arg1),
                 }
+            }
+            let mut positional = positional.into_iter();
 
-                if check_entry_exists(&entry_name, &entries) {
+            let entry_name: String;
+            if let Some(arg1) = positional.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
+
+            validate_name(&entry_name, &entries)?;
+
+            match entries.iter().find(|entry| entry.is_called(&entry_name)) {
+                Some(entry) if entry.is_personal() => {
                     println!("[add] map entry with this name already exists, editing");
-                    edit(&config, &entry_name, &mut entries, "no-new-category");
-                    return;
+                    edit(&config, &entry_name, &mut entries, "no-new-category")?;
+                    return Ok(0);
                 }
-
-                let entry_category: String;
-                if let Some(arg1) = args.next() {
-                    entry_category = arg1;
-                } else {
-                    entry_category = "default".to_string();
-                    println!("[add] adding '{}' with default category", entry_name);
+                // A personal command may shadow the one of a shared layer
+                Some(entry) => {
+                    eprintln!(
+                        "[layers] '{}' will shadow '{}' of layer '{}'",
+                        entry_name, entry.name, entry.layer
+                    );
+                    entries.retain(|entry| entry.is_personal() || !entry.is_called(&entry_name));
                 }
+                None => {}
+            }
 
-                let mut char_sequence = gen_char_sequence();
-                while check_sequence_exists(&char_sequence, &mut entries) {
-                    println!(
-                        "filehash {} already existed!, generating again..",
-                        &char_sequence
-                    );
-                    char_sequence = gen_char_sequence();
+            let entry_category: String;
+            if let Some(arg1) = positional.next() {
+                entry_category = arg1;
+            } else {
+                entry_category = "default".to_string();
+                println!("[add] adding '{}' with default category", entry_name);
+            }
+            if let Err(e) = namespaces::validate_path(&entry_category) {
+                return Err(Error::Usage(e.to_string()));
+            }
+
+            let content = match templates::resolve_template(
+                &config,
+                template_name.as_deref(),
+                &entry_category,
+            ) {
+                _ if is_composite => composite::TEMPLATE.to_string(),
+                Ok(Some((template_name, template))) => {
+                    println!("[add] using template '{}'", template_name);
+                    templates::render(&template, &entry_name, &entry_category, &config.author)
                 }
+                Ok(None) => "".to_string(),
+                Err(template_name) => {
+                    return Err(Error::NotFound(format!(
+                        "template '{}' doesn't exist",
+                        template_name
+                    )));
+                }
+            };
 
-                add(
-                    MapEntry {
-                        name: entry_name.to_string(),
-                        category: entry_category.clone(),
-                        filehash: char_sequence,
-                    },
-                    &mut entries,
+            let mut char_sequence = gen_char_sequence();
+            while check_sequence_exists(&char_sequence, &mut entries) {
+                println!(
+                    "filehash {} already existed!, generating again..",
+                    &char_sequence
                 );
+                char_sequence = gen_char_sequence();
+            }
+
+            add(
+                MapEntry::new(
+                    entry_name.to_string(),
+                    entry_category.clone(),
+                    char_sequence,
+                ),
+                &mut entries,
+                &content,
+            )?;
+
+            if is_composite {
+                if let Some(entry) = get_entry_by_name(&entry_name, &entries) {
+                    let mut entry_meta = meta::read_meta(entry);
+                    entry_meta.set("kind", "composite");
+                    meta::save_meta(entry, &entry_meta)?;
+                }
+            }
+
+            edit(&config, &entry_name, &mut entries, &entry_category)?;
+            ext(&entry_name, &mut entries)?;
+        }
+        "edit" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
 
-                ext(&entry_name, &mut entries);
+            let entry_category: String;
+            if let Some(arg1) = args.next() {
+                if let Err(e) = namespaces::validate_path(&arg1) {
+                    return Err(Error::Usage(e.to_string()));
+                }
+                entry_category = arg1;
+                println!("[edit] changing category to '{}'", entry_category);
+            } else {
+                entry_category = "no-new-category".to_string();
+            }
 
-                edit(&config, &entry_name, &mut entries, &entry_category);
+            edit(&config, &entry_name, &mut entries, &entry_category)?;
+        }
+        "ext" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
+
+            ext(&entry_name, &mut entries)?;
+        }
+        "rm" => {
+            remove(&mut args, &mut entries)?;
+        }
+        "ls" | "list" => {
+            let (format, rest) = output::take_format(args.collect())?;
+            let mut options = listing::ListOptions {
+                filter: String::new(),
+                sort: listing::SortKey::Category,
+                long: false,
+                color: listing::use_color(&config.color).unwrap_or(false),
+                pattern: None,
+                interpreter: None,
+                format,
+                layered: !config.layers.is_empty(),
+            };
+            let mut rest = rest.into_iter();
+            while let Some(arg1) = rest.next() {
+                match &arg1[..] {
+                    "-l" | "--long" => options.long = true,
+                    "-s" | "--sort" => {
+                        match rest.next().as_deref().and_then(listing::SortKey::parse) {
+                            Some(sort) => options.sort = sort,
+                            None => {
+                                return Err(Error::Usage(
+                                    "expected --sort name|category|recent|runs".to_string(),
+                                ))
+                            }
+                        }
+                    }
+                    "--color" => match rest.next().as_deref().and_then(listing::use_color) {
+                        Some(color) => options.color = color,
+                        None => {
+                            return Err(Error::Usage(
+                                "expected --color auto|always|never".to_string(),
+                            ))
+                        }
+                    },
+                    "-m" | "--match" => options.pattern = rest.next(),
+                    "-i" | "--interpreter" => options.interpreter = rest.next(),
+                    _ => options.filter = arg1,
+                }
+            }
+            listing::list(&entries, &options);
+            layers::warn_shadowed(&config);
+        }
+        "lsc" => {
+            let (format, _) = output::take_format(args.collect())?;
+            listing::list_categories(&entries, format);
+        }
+        "info" => {
+            let (format, rest) = output::take_format(args.collect())?;
+            let entry_name = match rest.into_iter().next() {
+                Some(arg1) => arg1,
+                None => return Err(Error::Usage("no name supplied".to_string())),
+            };
+            match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => info::print_info(entry, format),
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )))
+                }
             }
-            "edit" => {
-                let entry_name: String;
-                if let Some(arg1) = args.next() {
-                    entry_name = arg1;
-                } else {
-                    println!("[edit] no name supplied, exiting.");
-                    return;
+        }
+        "history" => {
+            let (format, rest) = output::take_format(args.collect())?;
+            let mut limit = 20;
+            let mut name: Option<String> = None;
+            let mut rest = rest.into_iter();
+            while let Some(arg1) = rest.next() {
+                match &arg1[..] {
+                    "-n" => match rest.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(n)) => limit = n,
+                        _ => return Err(Error::Usage("expected the number of runs".to_string())),
+                    },
+                    _ => name = Some(arg1),
                 }
+            }
+            history::print_history(name.as_deref(), limit, &entries, format)?;
+        }
+        "meta" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
 
-                let entry_category: String;
-                if let Some(arg1) = args.next() {
-                    entry_category = arg1;
-                    println!("[edit] changing category to '{}'", entry_category);
-                } else {
-                    entry_category = "no-new-category".to_string();
+            let entry = match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => entry,
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )));
                 }
+            };
 
-                edit(&config, &entry_name, &mut entries, &entry_category);
+            match (args.next(), args.next()) {
+                (None, _) => {
+                    layers::check_writable(entry)?;
+                    meta::edit_meta(&config, entry)?
+                }
+                (Some(key), None) => meta::print_meta(entry, &key),
+                (Some(key), Some(value)) => {
+                    layers::check_writable(entry)?;
+                    let mut entry_meta = meta::read_meta(entry);
+                    entry_meta.set(&key, &value);
+                    meta::save_meta(entry, &entry_meta)?;
+                    println!("[meta] set '{}' of '{}'", key, entry_name);
+                }
             }
-            "ext" => {
-                let entry_name: String;
-                if let Some(arg1) = args.next() {
-                    entry_name = arg1;
-                } else {
-                    println!("[ext] no name supplied, exiting.");
-                    return;
+        }
+        "env" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
+
+            let entry = match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => entry,
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )));
                 }
+            };
 
-                ext(&entry_name, &mut entries);
+            let entry_meta = meta::read_meta(entry);
+            match envs::get_command_env(&config, entry, &entry_meta) {
+                Ok(command_env) => envs::print_env(&command_env),
+                Err(e) => return Err(Error::Usage(e.to_string())),
             }
-            "rm" => {
-                remove(&mut args, &mut entries);
+            for (var, name) in secrets::get_declared_secrets(&entry_meta) {
+                println!("{: <16}(secret '{}')", var, name);
             }
-            "ls" | "list" => {
-                let category: String = if let Some(arg1) = args.next() {
-                    arg1
-                } else {
-                    "".to_string()
-                };
-                list(&entries, &category);
+        }
+        "protect" | "unprotect" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
             }
-            "lsc" => {
-                list_categories(&entries);
+
+            let entry = match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => entry,
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )));
+                }
+            };
+            layers::check_writable(entry)?;
+
+            if arg == "protect" {
+                let mut warning: Vec<String> = Vec::new();
+                let mut type_name = false;
+                for arg1 in args.by_ref() {
+                    if arg1 == "--type-name" {
+                        type_name = true;
+                    } else {
+                        warning.push(arg1);
+                    }
+                }
+                confirm::protect(entry, &warning.join(" "), type_name)?;
+            } else {
+                confirm::unprotect(entry)?;
             }
-            "interactive" | "int" => {
-                /* Setup ncurses. */
-                initscr();
-                raw();
 
-                /* Allow for extended keyboard (like F1). */
-                keypad(stdscr(), true);
-                noecho();
+            // Refresh an exported wrapper, it has to call pxc for protected commands
+            if get_wrapper_path(&entry_name).exists() {
+                ext(&entry_name, &mut entries)?;
+            }
+        }
+        "secret" => {
+            let action = args.next().unwrap_or_default();
+            if action == "ls" || action == "list" {
+                secrets::list_secrets(&config, &entries)?;
+                return Ok(0);
+            }
 
-                /* Invisible cursor. */
-                curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+            let secret_name: String;
+            if let Some(arg1) = args.next() {
+                secret_name = arg1;
+            } else {
+                return Err(Error::Usage(
+                    "usage: secret (set | get | rm) <name> | secret ls".to_string(),
+                ));
+            }
 
-                let mut search_word = "".to_string();
+            match &action[..] {
+                "set" => secrets::set_secret(&config, &secret_name)?,
+                "get" => secrets::get_secret(&config, &secret_name)?,
+                "rm" | "remove" => secrets::remove_secret(&config, &secret_name)?,
+                _ => return Err(Error::Usage(format!("unknown action '{}'", action))),
+            }
+        }
+        "templates" => {
+            templates::list_templates(&config);
+        }
+        "template" => {
+            let template_name: String;
+            if let Some(arg1) = args.next() {
+                template_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
 
-                mvprintw(LINES() - 1, 0, "Press Escape to exit").unwrap();
-                refresh();
+            templates::edit_template(&config, &template_name)?;
+        }
+        "revisions" | "revs" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
 
-                /* Get the screen bounds. */
-                let mut max_x = 0;
-                let mut max_y = 0;
-                getmaxyx(stdscr(), &mut max_y, &mut max_x);
+            match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => revisions::list_revisions(entry),
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )))
+                }
+            }
+        }
+        "diff" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
 
-                let mut in_loop = true;
+            let rev: Option<u32> = match args.next() {
+                Some(arg1) => match arg1.parse::<u32>() {
+                    Ok(rev) => Some(rev),
+                    Err(_) => {
+                        return Err(Error::Usage(format!("invalid revision '{}'", arg1)));
+                    }
+                },
+                None => None,
+            };
+
+            match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => revisions::diff(entry, rev)?,
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )))
+                }
+            }
+        }
+        "revert" => {
+            let entry_name: String;
+            if let Some(arg1) = args.next() {
+                entry_name = arg1;
+            } else {
+                return Err(Error::Usage("no name supplied".to_string()));
+            }
 
-                let mut key_e = 'e' as i32;
+            let rev: u32 = match args.next().map(|arg1| arg1.parse::<u32>()) {
+                Some(Ok(rev)) => rev,
+                _ => {
+                    return Err(Error::Usage("no valid revision supplied".to_string()));
+                }
+            };
+
+            match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => {
+                    layers::check_writable(entry)?;
+                    revisions::revert(entry, rev, config.revisions)?;
+                    // The script changed without saving the map
+                    search::update_index(&entries);
+                }
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )))
+                }
+            }
+        }
+        "interactive" | "int" => {
+            /* Setup ncurses. Drawing errors (text with a NUL byte) are ignored. */
+            initscr();
+            raw();
 
-                let mut last_max_y = 0;
+            /* Allow for extended keyboard (like F1). */
+            keypad(stdscr(), true);
+            noecho();
 
-                while in_loop == true {
-                    let mut ch = getch();
+            /* Invisible cursor. */
+            curs_set(CURSOR_VISIBILITY::CURSOR_INVISIBLE);
 
-                    match ch {
-                        27 => {
-                            // escape
-                            in_loop = false;
-                        }
-                        263 => {
-                            // backspace
-                            let mut chars = search_word.chars();
-                            chars.next_back();
-                            search_word = chars.as_str().to_string();
-                            let mut search_word_display = "".to_string();
-                            search_word_display.push_str("search: '");
-                            search_word_display.push_str(&search_word.clone());
-                            search_word_display.push_str("'");
-                            mvprintw(2, 0, "                                                                  ").unwrap();
-
-                            mvprintw(2, 0, &search_word_display.as_str()).unwrap();
-
-                            let mut print_y = 3;
-
-                            for i in 3..last_max_y + 1 {
-                                mvprintw(i, 0, "                                                                  ").unwrap();
-                            }
-                            for entry in find_entries_containing(&entries, search_word.clone()) {
-                                print_y += 1;
-                                mvprintw(print_y, 0, "                                                                  ").unwrap();
-                                if print_y == 1 {
-                                    mvprintw(print_y, 0, &("-> ".to_owned() + &entry.as_str()))
-                                        .unwrap();
-                                } else {
-                                    mvprintw(print_y, 0, &entry.as_str()).unwrap();
-                                }
-                            }
-                            last_max_y = print_y;
-                        }
-                        10 => {
-                            //enter
-                            //endwin();
-                            //in_loop = false;
-                            // Run command if exact match
+            let mut search_word = "".to_string();
+
+            let _ = mvprintw(LINES() - 1, 0, "Press Escape to exit");
+            refresh();
+
+            /* Get the screen bounds. */
+            let mut max_x = 0;
+            let mut max_y = 0;
+            getmaxyx(stdscr(), &mut max_y, &mut max_x);
+
+            let mut in_loop = true;
+
+            let mut last_max_y = 0;
 
-                            let mut found_entries =
-                                find_entries_containing(&entries, search_word.clone());
-                            found_entries.sort_by(|a, b| a.len().cmp(&b.len()));
+            let mut selected: Option<String> = None;
 
-                            run_cmd(found_entries.get(0).unwrap(), &mut args, &entries);
+            while in_loop {
+                let ch = getch();
 
+                match ch {
+                    27 => {
+                        // escape
+                        in_loop = false;
+                    }
+                    263 => {
+                        // backspace
+                        let mut chars = search_word.chars();
+                        chars.next_back();
+                        search_word = chars.as_str().to_string();
+                        let mut search_word_display = "".to_string();
+                        search_word_display.push_str("search: '");
+                        search_word_display.push_str(&search_word.clone());
+                        search_word_display.push('\'');
+                        let _ = mvprintw(
+                            2,
+                            0,
+                            "                                                                  ",
+                        );
+
+                        let _ = mvprintw(2, 0, search_word_display.as_str());
+
+                        let mut print_y = 3;
+
+                        for i in 3..last_max_y + 1 {
+                            let _ = mvprintw(i, 0, "                                                                  ");
+                        }
+                        for entry in find_entries_containing(&entries, search_word.clone()) {
+                            print_y += 1;
+                            let _ = mvprintw(print_y, 0, "                                                                  ");
+                            if print_y == 1 {
+                                let _ = mvprintw(print_y, 0, &("-> ".to_owned() + entry.as_str()));
+                            } else {
+                                let _ = mvprintw(print_y, 0, entry.as_str());
+                            }
+                        }
+                        last_max_y = print_y;
+                    }
+                    10 => {
+                        //enter
+                        // Run the closest match after leaving ncurses, the command
+                        // may need the terminal (confirmation, prompts)
+                        let mut found_entries =
+                            find_entries_containing(&entries, search_word.clone());
+                        found_entries.sort_by_key(|a| a.len());
+
+                        if let Some(found) = found_entries.first() {
+                            selected = Some(found.clone());
                             in_loop = false;
                         }
-                        _ => {
-                            //search_word += ch.to_string().as_str();
-                            search_word +=
-                                std::char::from_u32(ch as u32).unwrap().to_string().as_str();
+                    }
+                    _ => {
+                        // Keys that aren't characters (e.g. ERR) are ignored
+                        match std::char::from_u32(ch as u32) {
+                            Some(c) => search_word.push(c),
+                            None => continue,
+                        }
 
-                            let mut search_word_display = "".to_string();
-                            search_word_display.push_str("search: '");
-                            search_word_display.push_str(&search_word.clone());
-                            search_word_display.push_str("'");
+                        let mut search_word_display = "".to_string();
+                        search_word_display.push_str("search: '");
+                        search_word_display.push_str(&search_word.clone());
+                        search_word_display.push('\'');
 
-                            mvprintw(2, 0, &search_word_display.as_str()).unwrap();
+                        let _ = mvprintw(2, 0, search_word_display.as_str());
 
-                            let mut print_y = 3;
+                        let mut print_y = 3;
 
-                            for i in 3..last_max_y + 1 {
-                                mvprintw(i, 0, "                                                                  ").unwrap();
-                            }
+                        for i in 3..last_max_y + 1 {
+                            let _ = mvprintw(i, 0, "                                                                  ");
+                        }
 
-                            let mut found_entries =
-                                find_entries_containing(&entries, search_word.clone());
-                            found_entries.sort_by(|a, b| a.len().cmp(&b.len()));
-
-                            for entry in found_entries {
-                                print_y += 1;
-                                mvprintw(print_y, 0, "                                                                  ").unwrap();
-                                if print_y == 1 {
-                                    mvprintw(print_y, 0, &("-> ".to_owned() + &entry.as_str()))
-                                        .unwrap();
-                                } else {
-                                    mvprintw(print_y, 0, &entry.as_str()).unwrap();
-                                }
+                        let mut found_entries =
+                            find_entries_containing(&entries, search_word.clone());
+                        found_entries.sort_by_key(|a| a.len());
+
+                        for entry in found_entries {
+                            print_y += 1;
+                            let _ = mvprintw(print_y, 0, "                                                                  ");
+                            if print_y == 1 {
+                                let _ = mvprintw(print_y, 0, &("-> ".to_owned() + entry.as_str()));
+                            } else {
+                                let _ = mvprintw(print_y, 0, entry.as_str());
                             }
-                            last_max_y = print_y;
                         }
+                        last_max_y = print_y;
                     }
                 }
-
-                endwin();
             }
-            _ => {
-                let cmd = arg;
 
-                // Run command if exact match
-                if check_entry_exists(&cmd, &entries) {
-                    run_cmd(&cmd, &mut args, &entries);
-                    return;
-                }
-
-                let possible_cmds = find_entries_containing(&entries, cmd);
+            endwin();
 
-                if possible_cmds.len() == 0 {
-                    println!("Command not found");
-                    return;
+            if let Some(selected) = selected {
+                return Ok(run::run_cmd(&selected, args.collect(), &entries, &config));
+            }
+        }
+        "run" => {
+            let mut jobs: Option<usize> = None;
+            let mut parallel = false;
+            let mut keep_going = false;
+            let mut category: Option<String> = None;
+            let mut pattern: Option<String> = None;
+            let mut target: Option<String> = None;
+            while let Some(arg1) = args.next() {
+                match &arg1[..] {
+                    "-j" | "--jobs" => match args.next().map(|j| j.parse::<usize>()) {
+                        Some(Ok(j)) if j > 0 => jobs = Some(j),
+                        _ => {
+                            return Err(Error::Usage(
+                                "expected the number of parallel jobs".to_string(),
+                            ));
+                        }
+                    },
+                    "-p" | "--parallel" => parallel = true,
+                    "-k" | "--keep-going" => keep_going = true,
+                    "-c" | "--category" => category = args.next(),
+                    "-m" | "--match" => pattern = args.next(),
+                    "--" => break,
+                    _ => {
+                        target = Some(arg1);
+                        break;
+                    }
                 }
+            }
 
-                if possible_cmds.len() > 1 {
-                    println!("Did you mean one of:");
+            let job_list: Vec<jobs::Job> = if category.is_some() || pattern.is_some() {
+                if let Some(target) = target {
+                    return Err(Error::Usage(format!("'{}': pass either a command or --category/--match, use -- before arguments", target)));
                 }
 
-                let mut choice_entries = Vec::new();
-                let mut counter = 0;
-                for cmd in possible_cmds {
-                    counter += 1;
-                    println!("{}. ->{}", counter, cmd);
-                    choice_entries.push(cmd);
+                // Every matching command on its own, with the same arguments
+                let cmdargs: Vec<String> = args.collect();
+                let mut selected: Vec<&MapEntry> = entries
+                    .iter()
+                    .filter(|entry| {
+                        category
+                            .as_ref()
+                            .is_none_or(|c| namespaces::in_namespace(&entry.category, c))
+                    })
+                    .filter(|entry| {
+                        pattern
+                            .as_ref()
+                            .is_none_or(|p| matches_pattern(p, &entry.name))
+                    })
+                    .collect();
+                selected.sort_by(|a, b| a.name.cmp(&b.name));
+
+                if selected.is_empty() {
+                    return Err(Error::NotFound("no matching commands".to_string()));
                 }
-                let mut input_text = String::new();
 
-                if choice_entries.len() > 1 {
-                    println!("select: ");
-                } else {
-                    println!("Press Enter to run {}", choice_entries.get(0).unwrap());
-                }
+                // Independent commands, a failure doesn't stop the others
+                keep_going = true;
+                selected
+                    .into_iter()
+                    .map(|entry| jobs::Job {
+                        name: entry.name.clone(),
+                        args: cmdargs.clone(),
+                        needs: Vec::new(),
+                    })
+                    .collect()
+            } else {
+                let target = match target {
+                    Some(target) => target,
+                    None => {
+                        return Err(Error::Usage("no target supplied".to_string()));
+                    }
+                };
+
+                let nodes = graph::build_graph(&target, &entries)?;
 
-                io::stdin()
-                    .read_line(&mut input_text)
-                    .expect("failed to read from stdin");
+                // The dependency graph runs in parallel by default
+                parallel = true;
 
-                let trimmed = input_text.trim();
-                match trimmed.parse::<u32>() {
-                    Ok(i) => run_cmd(
-                        &choice_entries.get(i as usize - 1).unwrap(),
-                        &mut args,
-                        &entries,
-                    ),
-                    Err(..) => {
-                        if trimmed == "" && choice_entries.len() == 1 {
-                            run_cmd(&choice_entries.get(0).unwrap(), &mut args, &entries)
+                // The arguments are only passed to the target
+                let mut target_args: Vec<String> = args.collect();
+                nodes
+                    .into_iter()
+                    .map(|node| jobs::Job {
+                        args: if node.name == target {
+                            std::mem::take(&mut target_args)
                         } else {
-                            println!("invalid option: {}", &trimmed);
-                        }
+                            Vec::new()
+                        },
+                        name: node.name,
+                        needs: node.needs,
+                    })
+                    .collect()
+            };
+
+            if !jobs::confirm_jobs(&job_list, &entries, &config) {
+                return Ok(error::own_code(error::EXIT_FAILURE));
+            }
+
+            let limit = match jobs {
+                Some(jobs) => jobs,
+                None if parallel => config.jobs,
+                None => 1,
+            };
+            let results = jobs::run_jobs(&job_list, limit, keep_going, &config);
+            jobs::print_summary(&results);
+            if results
+                .iter()
+                .any(|result| result.state != jobs::JobState::Succeeded)
+            {
+                return Ok(error::own_code(error::EXIT_FAILURE));
+            }
+        }
+        "schedule" => match args.next().as_deref() {
+            Some("ls") | None => schedule::list_schedules(&entries),
+            Some("rm") => match args.next() {
+                Some(arg1) => match get_entry_by_name(&arg1, &entries) {
+                    Some(entry) => {
+                        layers::check_writable(entry)?;
+                        schedule::remove_schedule(entry)?
+                    }
+                    None => {
+                        return Err(Error::NotFound(format!(
+                            "item with name '{}' doesn't exist",
+                            arg1
+                        )))
+                    }
+                },
+                None => return Err(Error::Usage("no name supplied".to_string())),
+            },
+            Some("sync") => {
+                let target = match (args.next().as_deref(), args.next()) {
+                    (None, _) => "crontab".to_string(),
+                    (Some("--target"), Some(target)) => target,
+                    _ => {
+                        return Err(Error::Usage(
+                            "usage: schedule sync [--target crontab|systemd-user]".to_string(),
+                        ));
                     }
                 };
+                schedule::sync(&entries, &target)?;
+            }
+            Some(entry_name) => match get_entry_by_name(entry_name, &entries) {
+                // The cron expression may be quoted or given as separate words
+                Some(entry) => {
+                    let expr: Vec<String> = args.collect();
+                    if expr.is_empty() {
+                        return Err(Error::Usage("no schedule supplied".to_string()));
+                    }
+                    layers::check_writable(entry)?;
+                    schedule::set_schedule(entry, &expr.join(" "))?;
+                }
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )))
+                }
+            },
+        },
+        "watch" => {
+            let entry_name = match args.next() {
+                Some(arg1) => arg1,
+                None => {
+                    return Err(Error::Usage("no name supplied".to_string()));
+                }
+            };
+
+            let mut patterns: Vec<String> = Vec::new();
+            let mut debounce = watch::DEFAULT_DEBOUNCE;
+            while let Some(arg1) = args.next() {
+                match &arg1[..] {
+                    "--path" => match args.next() {
+                        Some(path) => patterns.push(path.trim_start_matches("./").to_string()),
+                        None => {
+                            return Err(Error::Usage("no path supplied".to_string()));
+                        }
+                    },
+                    "--debounce" => match args.next().map(|ms| ms.parse::<u64>()) {
+                        Some(Ok(ms)) => debounce = Duration::from_millis(ms),
+                        _ => {
+                            return Err(Error::Usage(
+                                "expected the debounce time in milliseconds".to_string(),
+                            ));
+                        }
+                    },
+                    "--" => break,
+                    _ => {
+                        return Err(Error::Usage(format!(
+                            "unknown option '{}', use -- before arguments",
+                            arg1
+                        )));
+                    }
+                }
+            }
+            if patterns.is_empty() {
+                patterns.push("**".to_string());
+            }
+
+            match get_entry_by_name(&entry_name, &entries) {
+                Some(entry) => {
+                    let cmdargs: Vec<String> = args.collect();
+                    watch::watch(entry, &patterns, debounce, &cmdargs, &config);
+                }
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        entry_name
+                    )))
+                }
+            }
+        }
+        "alias" => match args.next().as_deref() {
+            Some("ls") | None => aliases::list_aliases(&entries),
+            Some("add") => match args.next() {
+                Some(entry_name) => {
+                    let new_aliases: Vec<String> = args.collect();
+                    if new_aliases.is_empty() {
+                        return Err(Error::Usage("no alias supplied".to_string()));
+                    }
+                    aliases::add_aliases(&entry_name, &new_aliases, &mut entries)?;
+                }
+                None => return Err(Error::Usage("no name supplied".to_string())),
+            },
+            Some("rm") => match args.next() {
+                Some(alias) => aliases::remove_alias(&alias, &mut entries)?,
+                None => return Err(Error::Usage("no alias supplied".to_string())),
+            },
+            Some(other) => return Err(Error::Usage(format!("unknown subcommand '{}'", other))),
+        },
+        "promote" => {
+            let mut name: Option<String> = None;
+            let mut to: Option<String> = None;
+            while let Some(arg1) = args.next() {
+                match &arg1[..] {
+                    "--to" => to = args.next(),
+                    _ => name = Some(arg1),
+                }
+            }
+            match (name, to) {
+                (Some(name), Some(to)) => layers::promote(&name, &to, &entries, &config)?,
+                _ => {
+                    return Err(Error::Usage(
+                        "usage: promote <name> --to <layer>".to_string(),
+                    ))
+                }
+            }
+        }
+        "remote" => match args.next().as_deref() {
+            Some("ls") | None => remotes::list()?,
+            Some("add") => match (args.next(), args.next()) {
+                (Some(name), Some(url)) => remotes::add(&name, &url, &config)?,
+                _ => return Err(Error::Usage("usage: remote add <name> <url>".to_string())),
+            },
+            Some("rm") => match args.next() {
+                Some(name) => remotes::remove(&name)?,
+                None => return Err(Error::Usage("no remote supplied".to_string())),
+            },
+            Some("update") => remotes::update(args.next().as_deref())?,
+            Some("diff") => remotes::diff(args.next().as_deref())?,
+            Some("accept") => remotes::accept(args.next().as_deref())?,
+            Some(other) => return Err(Error::Usage(format!("unknown subcommand '{}'", other))),
+        },
+        "sign" => match args.next() {
+            Some(arg1) => match get_entry_by_name(&arg1, &entries) {
+                Some(entry) => {
+                    layers::check_writable(entry)?;
+                    signing::sign(entry)?
+                }
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        arg1
+                    )))
+                }
+            },
+            None => return Err(Error::Usage("no name supplied".to_string())),
+        },
+        "trust" => match args.next().as_deref() {
+            Some("ls") | None => signing::list_trusted(),
+            Some("add") => match (args.next(), args.next()) {
+                (Some(name), Some(key)) => signing::trust(&name, &key)?,
+                _ => return Err(Error::Usage("usage: trust add <name> <key>".to_string())),
+            },
+            Some("rm") => match args.next() {
+                Some(name) => signing::untrust(&name)?,
+                None => return Err(Error::Usage("no key name supplied".to_string())),
+            },
+            Some(other) => return Err(Error::Usage(format!("unknown subcommand '{}'", other))),
+        },
+        "verify" => match args.next() {
+            Some(arg1) => match get_entry_by_name(&arg1, &entries) {
+                Some(entry) => signing::verify(std::slice::from_ref(entry), &config)?,
+                None => {
+                    return Err(Error::NotFound(format!(
+                        "item with name '{}' doesn't exist",
+                        arg1
+                    )))
+                }
+            },
+            None => signing::verify(&entries, &config)?,
+        },
+        "doctor" => {
+            let fix = match args.next().as_deref() {
+                None => false,
+                Some("--fix") => true,
+                Some(other) => {
+                    return Err(Error::Usage(format!("unknown option '{}'", other)));
+                }
+            };
+            doctor::doctor(entries, fix, config.assume_yes)?;
+        }
+        "grep" => {
+            let mut context = 2;
+            let mut ignore_case = false;
+            let mut pattern: Option<String> = None;
+            while let Some(arg1) = args.next() {
+                match &arg1[..] {
+                    "-i" => ignore_case = true,
+                    "-C" => match args.next().map(|n| n.parse::<usize>()) {
+                        Some(Ok(n)) => context = n,
+                        _ => {
+                            return Err(Error::Usage(
+                                "expected the number of context lines".to_string(),
+                            ));
+                        }
+                    },
+                    _ => pattern = Some(arg1),
+                }
+            }
+
+            match pattern {
+                Some(pattern) => search::grep(&pattern, context, ignore_case, &entries)?,
+                None => return Err(Error::Usage("no pattern supplied".to_string())),
+            }
+        }
+        "search" => {
+            let (format, words) = output::take_format(args.collect())?;
+            if words.first().map(String::as_str) == Some("--reindex") {
+                search::reindex(&entries);
+            } else if words.is_empty() {
+                return Err(Error::Usage("no words supplied".to_string()));
+            } else {
+                search::search(&words, &entries, format);
+            }
+        }
+        "graph" => {
+            let mut dot = false;
+            let mut target: Option<String> = None;
+            for arg1 in args.by_ref() {
+                match &arg1[..] {
+                    "--dot" => dot = true,
+                    _ => target = Some(arg1),
+                }
+            }
+
+            match target {
+                Some(target) => graph::print_graph(&target, &entries, dot)?,
+                None => return Err(Error::Usage("no name supplied".to_string())),
+            }
+        }
+        "dry" => match args.next() {
+            Some(arg1) => {
+                let cmd = select_cmd(arg1, &entries)?;
+                run::dry_run(&cmd, args.collect(), &entries, &config)?;
+            }
+            None => return Err(Error::Usage("missing command name".to_string())),
+        },
+        _ => {
+            let cmd = select_cmd(arg, &entries)?;
+
+            // --dry-run directly after the name, pass '-- --dry-run' to give it to the script
+            let mut cmdargs: Vec<String> = args.collect();
+            if cmdargs.first().map(String::as_str) == Some("--dry-run") {
+                cmdargs.remove(0);
+                run::dry_run(&cmd, cmdargs, &entries, &config)?;
+            } else {
+                return Ok(run::run_cmd(&cmd, cmdargs, &entries, &config));
             }
         }
-    } else {
-        help();
     }
-}
 
-fn find_entries_containing(mut entries: &Vec<MapEntry>, mut chars: String) -> Vec<String> {
-    return entries
-        .clone()
-        .into_iter()
-        .filter(|entry| entry.name.contains(&chars))
-        .map(|entry| entry.name)
-        .collect();
+    Ok(0)
 }
 
-fn run_cmd(arg: &str, args: &mut core::iter::Skip<crate::env::Args>, entries: &[MapEntry]) {
-    match get_entry_by_name(arg, entries) {
-        Some(ent) => {
-            println!("Running command '{}' with filehash: {}", arg, ent.filehash);
+// Resolve a command name: an exact match, otherwise ask which of the commands
+// containing the name was meant
+fn select_cmd(cmd: String, entries: &[MapEntry]) -> Result<String, Error> {
+    // Names and aliases are looked up exactly before anything fuzzy
+    if let Some(entry) = get_entry_by_name(&cmd, entries) {
+        return Ok(entry.name.clone());
+    }
+
+    // restart runs k8s/prod/restart as long as no other namespace has a restart
+    if let [entry] = namespaces::find_by_leaf(&cmd, entries)[..] {
+        return Ok(entry.name.clone());
+    }
 
-            let cmdpath = format!("{}/cmd/{}", get_pxc_path(), ent.filehash);
+    let possible_cmds = find_entries_containing(entries, cmd.clone());
 
-            let cmdargs = args.collect::<Vec<_>>().join(" ");
-            println!("Command arguments: {}", cmdargs);
+    if possible_cmds.is_empty() {
+        return Err(Error::NotFound(format!("command '{}' not found", cmd)));
+    }
 
-            let status = execute_command(&cmdpath, &cmdargs);
+    if possible_cmds.len() > 1 {
+        println!("Did you mean one of:");
+    }
 
-            match status {
-                Ok(status_code) => {
-                    if !status_code.success() {
-                        println!("Command execution failed with status: {}", status_code);
-                    }
-                }
-                Err(e) => {
-                    println!("Failed to run command: {}", e);
-                }
-            }
-        }
-        None => println!("Command '{}' not found", arg),
+    for (counter, cmd) in possible_cmds.iter().enumerate() {
+        println!("{}. ->{}", counter + 1, cmd);
     }
-}
+    let mut input_text = String::new();
 
-fn execute_command(cmdpath: &str, cmdargs: &str) -> Result<ExitStatus, String> {
-    let command_str = format!("{} {}", cmdpath, cmdargs);
+    if possible_cmds.len() > 1 {
+        println!("select: ");
+    } else {
+        println!("Press Enter to run {}", possible_cmds[0]);
+    }
 
-    // Use sh -c for flexibility, but consider possible security risks
-    let status = Command::new("sh")
-        .arg("-c")
-        .arg(command_str.clone()) // Clone here to avoid moving it
-        .status();
+    io::stdin()
+        .read_line(&mut input_text)
+        .map_err(|e| Error::io("failed to read from stdin".to_string(), e))?;
 
-    match status {
-        Ok(status_code) => Ok(status_code),
-        Err(e) => Err(format!(
-            "Failed to execute command '{}': {}",
-            command_str, e
-        )),
+    let trimmed = input_text.trim();
+    let selected = match trimmed.parse::<usize>() {
+        Ok(i) if i >= 1 => possible_cmds.get(i - 1),
+        Ok(_) => None,
+        Err(..) if trimmed.is_empty() && possible_cmds.len() == 1 => possible_cmds.first(),
+        Err(..) => None,
+    };
+
+    selected
+        .cloned()
+        .ok_or_else(|| Error::Usage(format!("invalid option: {}", trimmed)))
+}
+
+// Shell style pattern match of a whole name, * matches any text and ? one character
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last * and the name position it matched up to
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p + 1, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
     }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn find_entries_containing(entries: &[MapEntry], chars: String) -> Vec<String> {
+    entries
+        .iter()
+        .filter(|entry| entry.name.contains(&chars))
+        .map(|entry| entry.name.clone())
+        .collect()
 }
 
 fn get_entry_by_name<'a>(entry_name: &str, entries: &'a [MapEntry]) -> Option<&'a MapEntry> {
-    entries.iter().find(|entry| entry.name == entry_name)
+    entries
+        .iter()
+        .find(|entry| entry.name == entry_name)
+        .or_else(|| entries.iter().find(|entry| entry.is_called(entry_name)))
 }
 
 fn read_config() -> Config {
@@ -418,207 +1383,349 @@ fn read_config() -> Config {
     // Default config values
     let mut config = Config {
         editor: "vim".to_string(),
+        revisions: 10,
+        author: env::var("USER").unwrap_or_default(),
+        template: "".to_string(),
+        category_templates: HashMap::new(),
+        env: Vec::new(),
+        secrets_keyfile: "".to_string(),
+        assume_yes: env::var("PXC_YES").is_ok_and(|v| v == "1"),
+        timeout: None,
+        retries: None,
+        jobs: thread::available_parallelism().map_or(1, |n| n.get()),
+        color: "auto".to_string(),
+        layers: Vec::new(),
+        verify: "warn".to_string(),
+        layer_verify: HashMap::new(),
     };
 
     // Check if config directory exists, if not, create it
     if !Path::new(&config_path).exists() {
         if let Err(e) = fs::create_dir_all(&config_path) {
-            println!("Error when creating dir: {}", e);
+            eprintln!("Error when creating dir: {}", e);
         }
     }
 
     // Read the config file if it exists
     if let Ok(map_lines) = read_lines(&config_filepath) {
-        for line in map_lines.flatten() {
+        for line in map_lines.map_while(Result::ok) {
             if let Some((key, value)) = line.split_once(';') {
                 match key {
                     "editor" => {
                         config.editor = value.to_string();
                     }
-                    _ => {}
+                    "revisions" => match value.parse::<usize>() {
+                        Ok(revisions) => config.revisions = revisions,
+                        Err(_) => eprintln!("[config] invalid revisions value '{}'", value),
+                    },
+                    "author" => {
+                        config.author = value.to_string();
+                    }
+                    "template" => {
+                        config.template = value.to_string();
+                    }
+                    "secrets_keyfile" => {
+                        config.secrets_keyfile = value.to_string();
+                    }
+                    "jobs" => match value.parse::<usize>() {
+                        Ok(jobs) if jobs > 0 => config.jobs = jobs,
+                        _ => eprintln!("[config] invalid jobs value '{}'", value),
+                    },
+                    "layer" => match value.split_once(';') {
+                        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+                            config.layers.push((name.to_string(), path.to_string()))
+                        }
+                        _ => eprintln!("[config] invalid layer value '{}'", value),
+                    },
+                    "verify" => match value {
+                        "off" | "warn" | "refuse" => config.verify = value.to_string(),
+                        _ => eprintln!("[config] invalid verify value '{}'", value),
+                    },
+                    "color" => match value {
+                        "auto" | "always" | "never" => config.color = value.to_string(),
+                        _ => eprintln!("[config] invalid color value '{}'", value),
+                    },
+                    _ => {
+                        // template.<category>;<template>
+                        if let Some(category) = key.strip_prefix("template.") {
+                            config
+                                .category_templates
+                                .insert(category.to_string(), value.to_string());
+                        }
+                        // verify.<layer>;off|warn|refuse
+                        if let Some(layer) = key.strip_prefix("verify.") {
+                            match value {
+                                "off" | "warn" | "refuse" => {
+                                    config
+                                        .layer_verify
+                                        .insert(layer.to_string(), value.to_string());
+                                }
+                                _ => eprintln!("[config] invalid {} value '{}'", key, value),
+                            }
+                        }
+                        if key == "env"
+                            || key == "envfile"
+                            || key.starts_with("env.")
+                            || key.starts_with("envfile.")
+                        {
+                            config.env.push((key.to_string(), value.to_string()));
+                        }
+                    }
                 }
             }
         }
     }
 
-    // Write the editor value to the config file if it has been modified
-    if !config.editor.is_empty() {
-        if let Err(e) = File::create(&config_filepath)
-            .and_then(|mut file| write!(file, "editor;{}\n", config.editor))
-        {
-            println!("Unable to write to config file: {}", e);
+    // Write the default values if there is no config file yet
+    if !Path::new(&config_filepath).exists() {
+        if let Err(e) = File::create(&config_filepath).and_then(|mut file| {
+            writeln!(file, "editor;{}", config.editor)?;
+            writeln!(file, "revisions;{}", config.revisions)
+        }) {
+            eprintln!("Unable to write to config file: {}", e);
         }
     }
 
     config
 }
 
-fn read_map_file() -> Vec<MapEntry> {
-    let map_file = format!("{}/map/pxc", get_pxc_path());
+// name;category;filehash[;alias,alias..], None if the line is malformed
+fn parse_map_line(line: &str) -> Option<MapEntry> {
+    let parts = line.split(';').collect::<Vec<_>>();
+    if parts.len() < 3 || parts.len() > 4 || parts[..3].iter().any(|part| part.is_empty()) {
+        return None;
+    }
+    let mut entry = MapEntry::new(
+        parts[0].to_string(),
+        parts[1].to_string(),
+        parts[2].to_string(),
+    );
+    entry.aliases = parts
+        .get(3)
+        .map(|aliases| {
+            aliases
+                .split(',')
+                .filter(|alias| !alias.is_empty())
+                .map(|alias| alias.to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+    Some(entry)
+}
 
-    // Try reading the file and processing the lines
-    match read_lines(map_file) {
-        Ok(map_lines) => {
-            let mut result = Vec::new();
-            for line in map_lines.flatten() {
-                let parts = line.split(';').collect::<Vec<_>>();
-                result.push(MapEntry {
-                    name: parts[0].to_string(),
-                    category: parts[1].to_string(),
-                    filehash: parts[2].to_string(),
-                });
-            }
-            result
+// The commands in the map of the store at store, as commands of the given layer
+fn read_map_file(store: &str, layer: &str) -> Result<Vec<MapEntry>, Error> {
+    let map_file = format!("{}/map/pxc", store);
+
+    let map_lines = read_lines(&map_file)
+        .map_err(|e| Error::io(format!("unable to read the map file '{}'", map_file), e))?;
+    let mut result = Vec::new();
+    for (number, line) in map_lines.map_while(Result::ok).enumerate() {
+        if line.trim().is_empty() {
+            continue;
         }
-        Err(_) => {
-            // In case of an error, return an empty Vec instead of returning a wrong type
-            println!("Error reading map file.");
-            Vec::new()
+        match parse_map_line(&line) {
+            Some(mut entry) => {
+                entry.layer = layer.to_string();
+                entry.store = store.to_string();
+                result.push(entry)
+            }
+            // Skipped, and dropped the next time the map is saved
+            None => eprintln!(
+                "[map] {}",
+                Error::Map {
+                    line: number + 1,
+                    text: line
+                }
+            ),
         }
     }
+    Ok(result)
 }
 
-fn ext(entry_name: &str, entries: &mut Vec<MapEntry>) {
-    if let Some(entry) = entries.iter().find(|e| e.name == entry_name) {
-        // Entry found
-        if !cfg!(unix) {
-            return;
+fn ext(entry_name: &str, entries: &mut [MapEntry]) -> Result<(), Error> {
+    let entry = entries
+        .iter()
+        .find(|e| e.is_called(entry_name))
+        .ok_or_else(|| Error::NotFound(format!("item with name '{}' doesn't exist", entry_name)))?;
+    if !cfg!(unix) {
+        return Ok(());
+    }
+
+    let cmdfilepath = entry.cmd_path();
+
+    // Protected commands go through pxc so the confirmation can't be skipped, composite
+    // commands have no script to run directly. Commands of other layers go through pxc
+    // so their signature is checked on every run, also after a later remote accept
+    // (personal commands are never checked, see signing::check_run).
+    let entry_meta = meta::read_meta(entry);
+    let line = if !entry.is_personal()
+        || confirm::is_protected(&entry_meta)
+        || composite::is_composite(&entry_meta)
+    {
+        format!("exec pxc \"{}\" \"$@\"", entry.name)
+    } else {
+        format!("exec \"{}\" \"$@\"", cmdfilepath.display())
+    };
+
+    // The same wrapper for the name and every alias, none replaces the one of another command
+    let wrapper_names: Vec<&String> = std::iter::once(&entry.name)
+        .chain(entry.aliases.iter())
+        .collect();
+    for wrapper_name in wrapper_names.iter() {
+        if let Some(other) = find_wrapper_collision(wrapper_name, &entry.name, entries) {
+            return Err(Error::Usage(format!(
+                "the exported wrapper '{}' of '{}' would replace the one of '{}'",
+                get_wrapper_path(wrapper_name).display(),
+                entry.name,
+                other.name
+            )));
         }
+    }
+    for wrapper_name in wrapper_names {
+        write_wrapper(wrapper_name, &line)?;
+    }
+    Ok(())
+}
 
-        // Construct paths using Path::join to avoid string concatenation
-        let extcmdpath = Path::new(&get_ext_path()).join(format!("{}.!", entry_name));
-        let cmdfilepath = Path::new(&get_pxc_path()).join("cmd").join(&entry.filehash);
+fn write_wrapper(wrapper_name: &str, line: &str) -> Result<(), Error> {
+    let extcmdpath = get_wrapper_path(wrapper_name);
 
-        // Try to create and write to the file
-        match File::create(&extcmdpath) {
-            Ok(file) => {
-                let mut file_buffer = BufWriter::new(file);
-                if let Err(e) = write!(file_buffer, "exec \"{}\" \"$@\"\n", cmdfilepath.display()) {
-                    println!(
-                        "[ext] failed to write to file '{}': {}",
-                        extcmdpath.display(),
-                        e
-                    );
-                    return;
-                }
+    File::create(&extcmdpath)
+        .and_then(|file| {
+            let mut file_buffer = BufWriter::new(file);
+            file_buffer.write_all(wrapper_content(line).as_bytes())?;
+            file_buffer.flush()
+        })
+        .and_then(|_| fs::set_permissions(&extcmdpath, Permissions::from_mode(0o777)))
+        .map_err(|e| Error::io(format!("unable to write '{}'", extcmdpath.display()), e))?;
 
-                // Set permissions on the file
-                if let Err(e) = fs::set_permissions(&extcmdpath, Permissions::from_mode(0o777)) {
-                    println!(
-                        "[ext] failed to set permissions on '{}': {}",
-                        extcmdpath.display(),
-                        e
-                    );
-                    return;
-                }
+    println!("[ext] exported command '{}'", extcmdpath.display());
+    Ok(())
+}
 
-                println!("[ext] exported command '{}.!'", entry_name);
-            }
-            Err(e) => {
-                println!(
-                    "[ext] failed to create file '{}': {}",
-                    extcmdpath.display(),
-                    e
-                );
-            }
-        }
-    } else {
-        println!("[ext] map entry with name '{}' doesn't exist!", entry_name);
+// Open path in the configured editor, fails if the editor can't be started or fails
+fn run_editor(config: &Config, path: &Path) -> Result<(), Error> {
+    let status = Command::new(&config.editor)
+        .arg(path)
+        .status()
+        .map_err(|e| {
+            Error::Exec(format!(
+                "failed to execute editor '{}': {}",
+                config.editor, e
+            ))
+        })?;
+    if !status.success() {
+        return Err(Error::Failed(format!(
+            "editor '{}' exited with {}",
+            config.editor, status
+        )));
     }
+    Ok(())
 }
 
-fn remove(args: &mut core::iter::Skip<crate::env::Args>, entries: &mut Vec<MapEntry>) {
+fn remove(
+    args: &mut core::iter::Skip<crate::env::Args>,
+    entries: &mut Vec<MapEntry>,
+) -> Result<(), Error> {
     let entry_name = match args.next() {
         Some(arg1) => arg1,
+        None => return Err(Error::Usage("no name supplied".to_string())),
+    };
+
+    // Check if the entry exists in the map
+    let pos = match entries.iter().position(|x| x.is_called(&entry_name)) {
+        Some(pos) => pos,
         None => {
-            println!("[rm] no name supplied, exiting.");
-            return;
+            return Err(Error::NotFound(format!(
+                "map entry with name '{}' doesn't exist",
+                entry_name
+            )))
         }
     };
 
-    // Check if the entry exists in the map
-    if !check_entry_exists(&entry_name, &entries) {
-        println!("[rm] map entry with name '{}' doesn't exist!", entry_name);
-        return;
-    }
+    layers::check_writable(&entries[pos])?;
+    remove_entry(entries, pos);
 
-    // Find the filehash of the entry
-    let file_hash = entries
-        .iter()
-        .find(|entry| entry.name == entry_name)
-        .map(|entry| entry.filehash.clone())
-        .unwrap_or_else(|| {
-            println!("[rm] Unable to find file hash for entry '{}'", entry_name);
-            return "8723478546982389235".to_string(); // Default fallback value
-        });
+    // Save the updated map to file
+    save_map(entries)?;
+
+    println!("[rm] Removed '{}' successfully!", entry_name);
+    Ok(())
+}
+
+// Remove a command with its script, metadata, revisions and exported wrappers
+fn remove_entry(entries: &mut Vec<MapEntry>, pos: usize) {
+    let entry = entries.remove(pos);
 
     // Remove the corresponding command file if it exists
-    let pxcpath = format!("{}/cmd/{}", get_pxc_path(), file_hash);
-    if Path::new(&pxcpath).exists() {
-        if let Err(e) = fs::remove_file(&pxcpath) {
-            println!("[rm] Failed to remove command file '{}': {}", pxcpath, e);
+    let cmdpath = entry.cmd_path();
+    if cmdpath.exists() {
+        if let Err(e) = fs::remove_file(&cmdpath) {
+            eprintln!(
+                "[rm] Failed to remove command file '{}': {}",
+                cmdpath.display(),
+                e
+            );
         }
     }
 
-    // Remove the entry from the map
-    if let Some(pos) = entries.iter().position(|x| x.name == entry_name) {
-        entries.remove(pos);
-    } else {
-        println!("[rm] Entry '{}' not found in map", entry_name);
-    }
-
-    // Remove the external command file if it exists
-    let extpath = format!("{}/{}.pxc", get_ext_path(), entry_name);
-    if Path::new(&extpath).exists() {
-        if let Err(e) = fs::remove_file(&extpath) {
-            println!("[rm] Failed to remove external file '{}': {}", extpath, e);
+    revisions::remove_revisions(&entry);
+    meta::remove_meta(&entry);
+
+    // Remove the external command files of the name and all aliases
+    for wrapper_name in std::iter::once(&entry.name).chain(entry.aliases.iter()) {
+        let extpath = get_wrapper_path(wrapper_name);
+        if extpath.exists() {
+            if let Err(e) = fs::remove_file(&extpath) {
+                eprintln!(
+                    "[rm] Failed to remove external file '{}': {}",
+                    extpath.display(),
+                    e
+                );
+            }
         }
     }
-
-    // Save the updated map to file
-    save_map(entries);
-
-    println!("[rm] Removed '{}' successfully!", entry_name);
 }
 
-// Return true if the entry_name exists in the map file
+// Return true if the entry_name exists in the map file, as name or alias
 fn check_entry_exists(entry_name: &str, entries: &[MapEntry]) -> bool {
-    entries.iter().any(|entry| entry.name == entry_name)
+    entries.iter().any(|entry| entry.is_called(entry_name))
 }
 
-fn add(mut new_entry: MapEntry, entries: &mut Vec<MapEntry>) {
+fn add(mut new_entry: MapEntry, entries: &mut Vec<MapEntry>, content: &str) -> Result<(), Error> {
     if check_entry_exists(&new_entry.name, entries) {
-        println!("[add] map entry with this name already exists, this should not happen!");
-        return;
+        return Err(Error::Usage(format!(
+            "map entry with name '{}' already exists",
+            new_entry.name
+        )));
     }
 
     if new_entry.category.is_empty() {
         new_entry.category = "default".to_string();
     }
 
-    let cmd_path = Path::new(&get_pxc_path())
-        .join("cmd")
-        .join(&new_entry.filehash);
-
-    match File::create(&cmd_path) {
-        Ok(_) => {
-            if let Err(e) = fs::set_permissions(&cmd_path, fs::Permissions::from_mode(0o777)) {
-                println!(
-                    "[add] Error setting permissions for file '{}': {}",
-                    cmd_path.display(),
-                    e
-                );
-            }
-        }
-        Err(e) => {
-            println!("[add] Error creating file '{}': {}", cmd_path.display(), e);
-            return;
-        }
-    }
-
-    entries.push(new_entry);
+    let cmd_path = new_entry.cmd_path();
+
+    File::create(&cmd_path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|e| Error::io(format!("unable to write '{}'", cmd_path.display()), e))?;
+    // Scripts are private to the owner, they may contain credentials
+    fs::set_permissions(&cmd_path, fs::Permissions::from_mode(0o700)).map_err(|e| {
+        Error::io(
+            format!("unable to set permissions of '{}'", cmd_path.display()),
+            e,
+        )
+    })?;
+
+    // Personal commands come before the ones of other layers
+    let pos = entries
+        .iter()
+        .position(|entry| !entry.is_personal())
+        .unwrap_or(entries.len());
+    entries.insert(pos, new_entry);
 
-    save_map(entries);
+    save_map(entries)
 }
 
 fn get_pxc_path() -> String {
@@ -628,13 +1735,13 @@ fn get_pxc_path() -> String {
             pxc_path
                 .to_str()
                 .unwrap_or_else(|| {
-                    println!("Error converting path to string");
+                    eprintln!("Error converting path to string");
                     ""
                 })
                 .to_string()
         }
         None => {
-            println!("Unable to get home directory!");
+            eprintln!("Unable to get home directory!");
             "".to_string()
         }
     }
@@ -642,116 +1749,152 @@ fn get_pxc_path() -> String {
 
 fn get_ext_path() -> String {
     // path for externalized commands
-    return "/usr/local/bin/".to_string();
+    "/usr/local/bin/".to_string()
 }
 
-fn save_map(entries: &Vec<MapEntry>) {
+// The exported wrapper of a command or alias, k8s/prod/restart becomes k8s-prod-restart.!
+fn get_wrapper_path(name: &str) -> PathBuf {
+    Path::new(&get_ext_path()).join(format!("{}.!", name.replace('/', "-")))
+}
+
+// The command (other than owner) with a name or alias that has the same wrapper as name,
+// k8s/prod and k8s-prod would both be k8s-prod.!
+fn find_wrapper_collision<'a>(
+    name: &str,
+    owner: &str,
+    entries: &'a [MapEntry],
+) -> Option<&'a MapEntry> {
+    let wrapper_path = get_wrapper_path(name);
+    entries.iter().find(|entry| {
+        entry.name != owner
+            && std::iter::once(&entry.name)
+                .chain(entry.aliases.iter())
+                .any(|other| get_wrapper_path(other) == wrapper_path)
+    })
+}
+
+// A command name has to be a valid path, no subcommand and get a wrapper of its own
+fn validate_name(name: &str, entries: &[MapEntry]) -> Result<(), Error> {
+    namespaces::validate_path(name).map_err(Error::Usage)?;
+    if SUBCOMMANDS.contains(&name) {
+        return Err(Error::Usage(format!("'{}' is a pxc subcommand", name)));
+    }
+    if let Some(other) = find_wrapper_collision(name, name, entries) {
+        return Err(Error::Usage(format!(
+            "'{}' would have the same exported wrapper '{}' as '{}'",
+            name,
+            get_wrapper_path(name).display(),
+            other.name
+        )));
+    }
+    Ok(())
+}
+
+// An exported wrapper running line, the first line marks the store it belongs to
+fn wrapper_content(line: &str) -> String {
+    format!("{}{}\n{}\n", WRAPPER_MARKER, get_pxc_path(), line)
+}
+
+fn save_map(entries: &[MapEntry]) -> Result<(), Error> {
     if !cfg!(unix) {
-        return;
+        return Ok(());
     }
 
     let newfilepath = Path::new(&get_pxc_path()).join("map").join("pxc");
 
     if !newfilepath.exists() {
-        println!("[save] map file doesn't exist!");
-        return;
+        return Err(Error::Store(format!(
+            "map file '{}' doesn't exist",
+            newfilepath.display()
+        )));
     }
 
-    // Open the file in write mode (create or truncate it)
-    let file_buffer = File::create(&newfilepath).expect("unable to create file");
-    let mut writer = BufWriter::new(file_buffer);
+    // Commands of other layers are never written to the personal map
+    write_map_file(
+        &newfilepath,
+        entries.iter().filter(|entry| entry.is_personal()),
+    )
+    .map_err(|e| {
+        Error::io(
+            format!("unable to write the map file '{}'", newfilepath.display()),
+            e,
+        )
+    })?;
 
-    for entry in entries {
-        let entry_line = format!("{};{};{}", entry.name, entry.category, entry.filehash);
-        write!(writer, "{}\n", entry_line).expect("unable to write to file");
-    }
+    search::update_index(entries);
 
     println!("[save] file saved!");
+    Ok(())
 }
 
-fn print_cmd(entry_name: &str, entries: Vec<MapEntry>) {
-    let position_of_entry = entries.iter().position(|entry| entry.name == entry_name);
-    let cmdpath =
-        get_pxc_path().to_string() + "/cmd/" + &entries[position_of_entry.unwrap()].filehash;
-
-    if Path::new(&cmdpath).exists() {
-        if let Ok(map_lines) = read_lines(&cmdpath) {
-            for line in map_lines.flatten() {
-                println!("{}", line);
-            }
+// Write the map file (create or truncate it)
+fn write_map_file<'a>(
+    map_file: &Path,
+    entries: impl Iterator<Item = &'a MapEntry>,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(map_file)?);
+    for entry in entries {
+        let mut entry_line = format!("{};{};{}", entry.name, entry.category, entry.filehash);
+        if !entry.aliases.is_empty() {
+            entry_line.push_str(&format!(";{}", entry.aliases.join(",")));
         }
+        writeln!(writer, "{}", entry_line)?;
     }
+    writer.flush()
 }
 
-fn edit(config: &Config, entry_name: &str, entries: &mut Vec<MapEntry>, category_name: &str) {
-    if let Some(entry) = entries.iter_mut().find(|e| e.name == entry_name) {
-        if category_name != "no-new-category" {
-            entry.category = category_name.to_string();
-        }
-
-        println!(
-            "[edit] editing command '{}', file: {}",
-            entry_name, entry.filehash
-        );
-
-        let cmdpath = Path::new(&get_pxc_path()).join("cmd").join(&entry.filehash);
-
-        if let Err(e) = Command::new(&config.editor).arg(cmdpath).status() {
-            eprintln!("Failed to execute editor: {}", e);
-        }
+fn print_cmd(entry_name: &str, entries: &[MapEntry]) -> Result<(), Error> {
+    let entry = get_entry_by_name(entry_name, entries)
+        .ok_or_else(|| Error::NotFound(format!("item with name '{}' doesn't exist", entry_name)))?;
+    if composite::is_composite(&meta::read_meta(entry)) {
+        composite::print_steps(entry);
+        return Ok(());
+    }
+    let cmdpath = entry.cmd_path();
 
-        save_map(entries);
-    } else {
-        eprintln!("Entry '{}' not found.", entry_name);
+    let map_lines = read_lines(&cmdpath)
+        .map_err(|e| Error::io(format!("unable to read '{}'", cmdpath.display()), e))?;
+    for line in map_lines.map_while(Result::ok) {
+        println!("{}", line);
     }
+    Ok(())
 }
 
-fn get_categories(entries: &Vec<MapEntry>) -> Vec<String> {
-    let mut unique_categories = HashSet::new();
-
-    for entry in entries {
-        unique_categories.insert(entry.category.to_string());
+fn edit(
+    config: &Config,
+    entry_name: &str,
+    entries: &mut [MapEntry],
+    category_name: &str,
+) -> Result<(), Error> {
+    let entry = entries
+        .iter_mut()
+        .find(|e| e.is_called(entry_name))
+        .ok_or_else(|| Error::NotFound(format!("item with name '{}' doesn't exist", entry_name)))?;
+    layers::check_writable(entry)?;
+    if category_name != "no-new-category" {
+        entry.category = category_name.to_string();
     }
 
-    unique_categories.into_iter().collect()
-}
+    println!(
+        "[edit] editing command '{}', file: {}",
+        entry_name, entry.filehash
+    );
 
-fn list_categories(entries: &Vec<MapEntry>) {
-    let categories = get_categories(entries);
+    let cmdpath = entry.cmd_path();
 
-    println!("CATEGORIES");
-    println!("{}", "🭶".repeat(22));
-    for cat in categories {
-        println!("{}", cat);
-    }
-}
+    // Keep the script as it was before this edit, see revisions/diff/revert
+    revisions::snapshot(entry, config.revisions);
 
-fn list(entries: &Vec<MapEntry>, category_name: &str) {
-    println!("NAME\t\tCATEGORY\tFILE");
-    println!("🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶🭶");
+    run_editor(config, &cmdpath)?;
+    // Scripts of older stores may still be readable by everyone
+    fs::set_permissions(&cmdpath, fs::Permissions::from_mode(0o700)).map_err(|e| {
+        Error::io(
+            format!("unable to set permissions of '{}'", cmdpath.display()),
+            e,
+        )
+    })?;
 
-    // If category_name is empty, we need to group entries by category
-    let categories_to_process: Vec<String> = if category_name.is_empty() {
-        get_categories(entries) // Presumably returns a vector of unique categories
-    } else {
-        vec![category_name.to_string()]
-    };
-
-    // Iterate over the selected categories
-    for category in categories_to_process {
-        for entry in entries.iter() {
-            if entry.category == category {
-                println!(
-                    "{: <16}{: <16}{: <16}",
-                    entry.name, entry.category, entry.filehash
-                );
-            }
-        }
-        // Only print a newline between categories if we're listing multiple categories
-        if category_name.is_empty() {
-            println!();
-        }
-    }
+    save_map(entries)
 }
 
 fn read_lines<P>(filename: P) -> io::Result<std::io::Lines<BufReader<File>>>
@@ -763,6 +1906,7 @@ where
     Ok(reader.lines()) // Return the Lines iterator
 }
 
+#[allow(dead_code)]
 fn init() -> std::io::Result<()> {
     println!("[init] initializing pxc..");
 
@@ -775,19 +1919,19 @@ fn init() -> std::io::Result<()> {
         // pxc directory: root pxec directory
         let pxcpath = Path::new(&pxcpath); // Convert String to Path
         fs::create_dir_all(pxcpath).map_err(|e| {
-            println!("error when creating dir {}", e);
+            eprintln!("error when creating dir {}", e);
             e
         })?;
 
         // map directory: stores the mapping of command to script
         fs::create_dir_all(pxcpath.join("map")).map_err(|e| {
-            println!("error when creating map directory: {}", e);
+            eprintln!("error when creating map directory: {}", e);
             e
         })?;
 
         // commands directory: stores all script files
         fs::create_dir_all(pxcpath.join("cmd")).map_err(|e| {
-            println!("error when creating cmd directory: {}", e);
+            eprintln!("error when creating cmd directory: {}", e);
             e
         })?;
 
@@ -805,3 +1949,41 @@ fn init() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_pattern_matches_whole_names() {
+        assert!(matches_pattern("test-*", "test-unit"));
+        assert!(matches_pattern("test-*", "test-"));
+        assert!(matches_pattern("*db*", "ops/db/backup"));
+        assert!(matches_pattern("b?ild", "build"));
+        assert!(matches_pattern("*a*b", "aaxab"));
+        assert!(matches_pattern("**", ""));
+        assert!(!matches_pattern("test-*", "unit-test-x"));
+        assert!(!matches_pattern("b?ild", "bild"));
+        assert!(!matches_pattern("build", "build2"));
+        assert!(!matches_pattern("", "x"));
+    }
+
+    #[test]
+    fn wrapper_collisions_ignore_the_owner() {
+        let mut entry = MapEntry::new(
+            "k8s/prod".to_string(),
+            "ops".to_string(),
+            "AAAAAAAA".to_string(),
+        );
+        entry.aliases.push("kp".to_string());
+        let entries = vec![entry];
+        assert!(find_wrapper_collision("k8s-prod", "k8s-prod", &entries).is_some());
+        assert!(find_wrapper_collision("kp", "other", &entries).is_some());
+        assert!(find_wrapper_collision("k8s/prod", "k8s/prod", &entries).is_none());
+        assert!(find_wrapper_collision("k8s", "k8s", &entries).is_none());
+        assert!(validate_name("list", &[]).is_err());
+        assert!(validate_name("k8s-prod", &entries).is_err());
+        assert!(validate_name("a//b", &[]).is_err());
+        assert!(validate_name("list/all", &entries).is_ok());
+    }
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::{read_lines, run_editor, Config, MapEntry};
+
+// Per command metadata, stored as key;value lines in meta/<filehash>.
+// A key may appear more than once (e.g. one param line per parameter).
+pub struct Meta {
+    values: Vec<(String, String)>,
+}
+
+impl Meta {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.values
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+
+    // All key;value pairs in file order
+    pub fn get_entries(&self) -> Vec<(&str, &str)> {
+        self.values
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect()
+    }
+
+    // Replace all values of key with value
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.remove(key);
+        self.values.push((key.to_string(), value.to_string()));
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.values.retain(|(k, _)| k != key);
+    }
+}
+
+pub fn get_meta_path(entry: &MapEntry) -> PathBuf {
+    Path::new(&entry.store).join("meta").join(&entry.filehash)
+}
+
+pub fn read_meta(entry: &MapEntry) -> Meta {
+    let mut meta = Meta { values: Vec::new() };
+
+    if let Ok(meta_lines) = read_lines(get_meta_path(entry)) {
+        for line in meta_lines.map_while(Result::ok) {
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((key, value)) = line.split_once(';') {
+                meta.values.push((key.to_string(), value.to_string()));
+            }
+        }
+    }
+
+    meta
+}
+
+pub fn save_meta(entry: &MapEntry, meta: &Meta) -> Result<(), Error> {
+    let meta_path = get_meta_path(entry);
+
+    meta_path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::File::create(&meta_path))
+        .and_then(|mut file| {
+            for (key, value) in meta.values.iter() {
+                writeln!(file, "{};{}", key, value)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| Error::io(format!("unable to write '{}'", meta_path.display()), e))
+}
+
+pub fn remove_meta(entry: &MapEntry) {
+    let meta_path = get_meta_path(entry);
+    if meta_path.exists() {
+        if let Err(e) = fs::remove_file(&meta_path) {
+            eprintln!(
+                "[rm] Failed to remove metadata file '{}': {}",
+                meta_path.display(),
+                e
+            );
+        }
+    }
+}
+
+pub fn print_meta(entry: &MapEntry, key: &str) {
+    let meta = read_meta(entry);
+    for value in meta.get_all(key) {
+        println!("{}", value);
+    }
+}
+
+pub fn edit_meta(config: &Config, entry: &MapEntry) -> Result<(), Error> {
+    let meta_path = get_meta_path(entry);
+    if !meta_path.exists() {
+        save_meta(entry, &read_meta(entry))?;
+    }
+
+    println!("[meta] editing metadata of '{}'", entry.name);
+    run_editor(config, &meta_path)
+}
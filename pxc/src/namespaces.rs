@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use super::MapEntry;
+
+// Names and categories can be namespaced with slashes (k8s/prod/restart), every
+// part has to be non-empty
+pub fn validate_path(path: &str) -> Result<(), String> {
+    if path.split('/').any(|part| part.trim().is_empty()) {
+        return Err(format!(
+            "'{}' is not a valid path, parts between slashes can't be empty",
+            path
+        ));
+    }
+    if path.contains(';') {
+        return Err(format!("'{}' can't contain ';'", path));
+    }
+    Ok(())
+}
+
+// The last part of a namespaced name, k8s/prod/restart -> restart
+pub fn leaf(name: &str) -> &str {
+    name.rsplit('/').next().unwrap_or(name)
+}
+
+// True if path is the namespace itself or lies below it, a trailing slash is ignored
+pub fn in_namespace(path: &str, namespace: &str) -> bool {
+    let namespace = namespace.trim_end_matches('/');
+    namespace.is_empty()
+        || path == namespace
+        || (path.starts_with(namespace) && path[namespace.len()..].starts_with('/'))
+}
+
+// The commands whose name ends in the leaf name, used when a leaf name is run directly
+pub fn find_by_leaf<'a>(name: &str, entries: &'a [MapEntry]) -> Vec<&'a MapEntry> {
+    entries
+        .iter()
+        .filter(|entry| entry.name.contains('/') && leaf(&entry.name) == name)
+        .collect()
+}
+
+#[derive(Default)]
+struct TreeNode {
+    // Commands in this category and all categories below it
+    count: usize,
+    children: BTreeMap<String, TreeNode>,
+}
+
+fn print_children(node: &TreeNode, prefix: &str) {
+    let last_index = node.children.len().saturating_sub(1);
+    for (i, (part, child)) in node.children.iter().enumerate() {
+        let last = i == last_index;
+        println!(
+            "{}{}{} ({})",
+            prefix,
+            if last { "└── " } else { "├── " },
+            part,
+            child.count
+        );
+        print_children(
+            child,
+            &format!("{}{}", prefix, if last { "    " } else { "│   " }),
+        );
+    }
+}
+
+// Every category and namespace with the number of commands in it and below it, sorted
+pub fn category_counts(entries: &[MapEntry]) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for entry in entries {
+        let mut path = String::new();
+        for part in entry.category.split('/') {
+            if !path.is_empty() {
+                path.push('/');
+            }
+            path.push_str(part);
+            *counts.entry(path.clone()).or_insert(0) += 1;
+        }
+    }
+    counts.into_iter().collect()
+}
+
+// Print the categories as a tree, split at the slashes, with the number of commands
+pub fn print_category_tree(entries: &[MapEntry]) {
+    let mut root = TreeNode::default();
+    for entry in entries {
+        let mut node = &mut root;
+        for part in entry.category.split('/') {
+            node = node.children.entry(part.to_string()).or_default();
+            node.count += 1;
+        }
+    }
+
+    for (part, node) in root.children.iter() {
+        println!("{} ({})", part, node.count);
+        print_children(node, "");
+    }
+}
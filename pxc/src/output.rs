@@ -0,0 +1,187 @@
+use super::error::Error;
+
+// Output of the listing commands: the table for people, JSON or TSV for other tools
+#[derive(Clone, Copy, PartialEq)]
+pub enum Format {
+    Table,
+    Json,
+    Tsv,
+}
+
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    List(Vec<String>),
+    Null,
+}
+
+impl Value {
+    pub fn opt_str(value: Option<&str>) -> Value {
+        value.map_or(Value::Null, |value| Value::Str(value.to_string()))
+    }
+}
+
+// Remove --json and --format <f> from the arguments
+pub fn take_format(args: Vec<String>) -> Result<(Format, Vec<String>), Error> {
+    let mut format = Format::Table;
+    let mut rest = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--json" => format = Format::Json,
+            "--format" => {
+                format = match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("tsv") => Format::Tsv,
+                    Some("table") => Format::Table,
+                    _ => return Err(Error::Usage("expected --format json|tsv|table".to_string())),
+                }
+            }
+            _ => rest.push(arg),
+        }
+    }
+    Ok((format, rest))
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Str(text) => json_string(text),
+        Value::Int(number) => number.to_string(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::List(items) => {
+            let items: Vec<String> = items.iter().map(|item| json_string(item)).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Null => "null".to_string(),
+    }
+}
+
+fn json_object(fields: &[&str], row: &[Value]) -> String {
+    let members: Vec<String> = fields
+        .iter()
+        .zip(row.iter())
+        .map(|(field, value)| format!("{}:{}", json_string(field), json_value(value)))
+        .collect();
+    format!("{{{}}}", members.join(","))
+}
+
+// Tabs, newlines and backslashes are escaped so every row stays one line
+fn tsv_value(value: &Value) -> String {
+    let text = match value {
+        Value::Str(text) => text.clone(),
+        Value::Int(number) => number.to_string(),
+        Value::Bool(flag) => flag.to_string(),
+        Value::List(items) => items.join(","),
+        Value::Null => String::new(),
+    };
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+// JSON: an array with one object per row. TSV: a header with the field names, then
+// one line per row.
+pub fn print_rows(format: Format, fields: &[&str], rows: &[Vec<Value>]) {
+    match format {
+        Format::Json => {
+            if rows.is_empty() {
+                println!("[]");
+                return;
+            }
+            println!("[");
+            for (i, row) in rows.iter().enumerate() {
+                let separator = if i + 1 < rows.len() { "," } else { "" };
+                println!("  {}{}", json_object(fields, row), separator);
+            }
+            println!("]");
+        }
+        Format::Tsv => {
+            println!("{}", fields.join("\t"));
+            for row in rows {
+                let values: Vec<String> = row.iter().map(tsv_value).collect();
+                println!("{}", values.join("\t"));
+            }
+        }
+        // The callers print their own tables
+        Format::Table => {}
+    }
+}
+
+// A single object in JSON, a header and one row in TSV
+pub fn print_object(format: Format, fields: &[&str], row: Vec<Value>) {
+    match format {
+        Format::Json => println!("{}", json_object(fields, &row)),
+        _ => print_rows(format, fields, &[row]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn json_string_escapes_quotes_backslashes_and_control_characters() {
+        assert_eq!(json_string("plain ü"), "\"plain ü\"");
+        assert_eq!(json_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_string("\n\r\t\u{1}"), "\"\\n\\r\\t\\u0001\"");
+    }
+
+    #[test]
+    fn json_object_and_tsv_values() {
+        let row = vec![
+            Value::Str("x".to_string()),
+            Value::Int(-3),
+            Value::Bool(true),
+            Value::List(vec!["a".to_string(), "b".to_string()]),
+            Value::Null,
+        ];
+        assert_eq!(
+            json_object(&["s", "i", "b", "l", "n"], &row),
+            "{\"s\":\"x\",\"i\":-3,\"b\":true,\"l\":[\"a\",\"b\"],\"n\":null}"
+        );
+        assert_eq!(
+            tsv_value(&Value::Str("a\tb\\c\nd".to_string())),
+            "a\\tb\\\\c\\nd"
+        );
+        assert_eq!(tsv_value(&row[3]), "a,b");
+        assert_eq!(tsv_value(&Value::Null), "");
+    }
+
+    #[test]
+    fn take_format_removes_format_arguments() {
+        let (format, rest) =
+            take_format(args(&["a", "--json", "b"])).unwrap_or_else(|e| panic!("{}", e));
+        assert!(format == Format::Json);
+        assert_eq!(rest, ["a", "b"]);
+        let (format, rest) =
+            take_format(args(&["--format", "tsv"])).unwrap_or_else(|e| panic!("{}", e));
+        assert!(format == Format::Tsv);
+        assert!(rest.is_empty());
+        assert!(take_format(args(&["--format", "xml"])).is_err());
+        assert!(take_format(args(&["--format"])).is_err());
+    }
+}
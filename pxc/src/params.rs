@@ -0,0 +1,388 @@
+use std::fs;
+use std::path::Path;
+
+use super::meta::Meta;
+use super::MapEntry;
+
+// Marker for parameters declared in a header comment of the script, e.g.
+// # pxc:param port:int=8080 Port to listen on
+const HEADER_MARKER: &str = "pxc:param ";
+
+pub enum ParamType {
+    Str,
+    Int,
+    Float,
+    Bool,
+    Path,
+    Choice(Vec<String>),
+}
+
+pub struct Param {
+    pub name: String,
+    pub kind: ParamType,
+    pub required: bool,
+    pub default: Option<String>,
+    pub help: String,
+}
+
+fn format_type(kind: &ParamType) -> String {
+    match kind {
+        ParamType::Str => "string".to_string(),
+        ParamType::Int => "int".to_string(),
+        ParamType::Float => "float".to_string(),
+        ParamType::Bool => "bool".to_string(),
+        ParamType::Path => "path".to_string(),
+        ParamType::Choice(choices) => format!("choice({})", choices.join("|")),
+    }
+}
+
+fn parse_type(kind: &str) -> Result<ParamType, String> {
+    match kind {
+        "" | "str" | "string" => Ok(ParamType::Str),
+        "int" => Ok(ParamType::Int),
+        "float" => Ok(ParamType::Float),
+        "bool" => Ok(ParamType::Bool),
+        "path" => Ok(ParamType::Path),
+        _ => match kind
+            .strip_prefix("choice(")
+            .and_then(|choices| choices.strip_suffix(')'))
+        {
+            Some(choices) => Ok(ParamType::Choice(
+                choices.split('|').map(|c| c.to_string()).collect(),
+            )),
+            None => Err(format!("unknown parameter type '{}'", kind)),
+        },
+    }
+}
+
+// Parse a parameter declaration: <name>[:<type>][=<default>|!] [help text]
+// A trailing ! marks the parameter as required.
+pub fn parse_param(spec: &str) -> Result<Param, String> {
+    let spec = spec.trim();
+    let (decl, help) = match spec.split_once(char::is_whitespace) {
+        Some((decl, help)) => (decl, help.trim()),
+        None => (spec, ""),
+    };
+
+    let (decl, required) = match decl.strip_suffix('!') {
+        Some(decl) => (decl, true),
+        None => (decl, false),
+    };
+
+    let (decl, default) = match decl.split_once('=') {
+        Some((decl, default)) => (decl, Some(default.to_string())),
+        None => (decl, None),
+    };
+
+    let (name, kind) = decl.split_once(':').unwrap_or((decl, ""));
+
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(format!("invalid parameter name '{}'", name));
+    }
+
+    Ok(Param {
+        name: name.to_string(),
+        kind: parse_type(kind)?,
+        required,
+        default,
+        help: help.to_string(),
+    })
+}
+
+// Parameters declared in the metadata (param;<spec>), followed by the ones declared
+// in header comments of the script
+pub fn get_params(entry: &MapEntry, meta: &Meta) -> Result<Vec<Param>, String> {
+    let mut specs: Vec<String> = meta
+        .get_all("param")
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    let cmdpath = entry.cmd_path();
+    if let Ok(content) = fs::read_to_string(cmdpath) {
+        for line in content.lines() {
+            let line = line.trim_start();
+            if !(line.starts_with('#') || line.starts_with("//")) {
+                continue;
+            }
+            if let Some(pos) = line.find(HEADER_MARKER) {
+                specs.push(line[pos + HEADER_MARKER.len()..].to_string());
+            }
+        }
+    }
+
+    let mut params: Vec<Param> = Vec::new();
+    for spec in specs {
+        let param = parse_param(&spec)?;
+        if params.iter().any(|p| p.name == param.name) {
+            return Err(format!("parameter '{}' declared twice", param.name));
+        }
+        params.push(param);
+    }
+    Ok(params)
+}
+
+pub fn get_env_name(param: &Param) -> String {
+    format!("PXC_PARAM_{}", param.name.to_uppercase().replace('-', "_"))
+}
+
+fn validate(param: &Param, value: &str) -> Result<String, String> {
+    let valid = match &param.kind {
+        ParamType::Str => true,
+        ParamType::Int => value.parse::<i64>().is_ok(),
+        ParamType::Float => value.parse::<f64>().is_ok(),
+        ParamType::Bool => {
+            return match value {
+                "true" | "yes" | "1" => Ok("true".to_string()),
+                "false" | "no" | "0" => Ok("false".to_string()),
+                _ => Err(format!(
+                    "parameter '{}' expects a bool, got '{}'",
+                    param.name, value
+                )),
+            };
+        }
+        ParamType::Path => Path::new(value).exists(),
+        ParamType::Choice(choices) => choices.iter().any(|c| c == value),
+    };
+
+    if valid {
+        Ok(value.to_string())
+    } else if let ParamType::Path = param.kind {
+        Err(format!(
+            "parameter '{}': path '{}' doesn't exist",
+            param.name, value
+        ))
+    } else {
+        Err(format!(
+            "parameter '{}' expects {}, got '{}'",
+            param.name,
+            format_type(&param.kind),
+            value
+        ))
+    }
+}
+
+// Final argument list and the environment variables of the parameters
+pub type MappedArgs = (Vec<String>, Vec<(String, String)>);
+
+// Map the command line arguments onto the declared parameters. Parameters can be
+// given as --<name> <value>, --<name>=<value> or positionally in declaration order
+// (bool parameters only as --<name>), everything after -- and all surplus arguments are passed through unchanged.
+// Returns the final argument list (parameters in declaration order, then the
+// remaining arguments) and the PXC_PARAM_* environment variables.
+pub fn map_args(params: &[Param], args: Vec<String>) -> Result<MappedArgs, String> {
+    // Nothing to map, the arguments are passed on as they are, -- included
+    if params.is_empty() {
+        return Ok((args, Vec::new()));
+    }
+
+    let mut values: Vec<Option<String>> = params.iter().map(|_| None).collect();
+    let mut extra: Vec<String> = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            extra.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(named) = arg.strip_prefix("--") {
+            let (name, value) = match named.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (named, None),
+            };
+
+            if let Some(pos) = params.iter().position(|p| p.name == name) {
+                let value = match (value, &params[pos].kind) {
+                    (Some(value), _) => value,
+                    (None, ParamType::Bool) => "true".to_string(),
+                    (None, _) => match args.next() {
+                        Some(value) => value,
+                        None => return Err(format!("parameter '{}' expects a value", name)),
+                    },
+                };
+                values[pos] = Some(value);
+                continue;
+            }
+        }
+
+        let next_positional = params
+            .iter()
+            .zip(values.iter())
+            .position(|(p, v)| v.is_none() && !matches!(p.kind, ParamType::Bool));
+        match next_positional {
+            Some(pos) => values[pos] = Some(arg),
+            None => extra.push(arg),
+        }
+    }
+
+    let mut cmdargs: Vec<String> = Vec::new();
+    let mut envs: Vec<(String, String)> = Vec::new();
+    for (param, value) in params.iter().zip(values) {
+        let value = match value.or_else(|| param.default.clone()) {
+            Some(value) => validate(param, &value)?,
+            None if param.required => {
+                return Err(format!("missing required parameter '{}'", param.name))
+            }
+            None if matches!(param.kind, ParamType::Bool) => "false".to_string(),
+            None => "".to_string(),
+        };
+        envs.push((get_env_name(param), value.clone()));
+        cmdargs.push(value);
+    }
+    cmdargs.extend(extra);
+
+    Ok((cmdargs, envs))
+}
+
+pub fn print_help(entry: &MapEntry, meta: &Meta, params: &[Param]) {
+    let mut usage = format!("usage: pxc {}", entry.name);
+    for param in params {
+        if let ParamType::Bool = param.kind {
+            usage.push_str(&format!(" [--{}]", param.name));
+        } else if param.required {
+            usage.push_str(&format!(" <{}>", param.name));
+        } else {
+            usage.push_str(&format!(" [{}]", param.name));
+        }
+    }
+    println!("{} [args..]", usage);
+
+    if let Some(description) = meta.get("description") {
+        println!();
+        println!("{}", description);
+    }
+
+    let rows: Vec<(&str, String, String, &str)> = params
+        .iter()
+        .map(|param| {
+            let default = match (&param.default, param.required) {
+                (Some(default), _) => default.clone(),
+                (None, true) => "(required)".to_string(),
+                (None, false) => "".to_string(),
+            };
+            (
+                param.name.as_str(),
+                format_type(&param.kind),
+                default,
+                param.help.as_str(),
+            )
+        })
+        .collect();
+
+    // Columns are at least 16 wide and grow with long types or defaults
+    let name_width = rows.iter().map(|r| r.0.len() + 1).fold(16, usize::max);
+    let type_width = rows.iter().map(|r| r.1.len() + 1).fold(16, usize::max);
+    let default_width = rows.iter().map(|r| r.2.len() + 1).fold(16, usize::max);
+
+    println!();
+    println!(
+        "{: <name_width$}{: <type_width$}{: <default_width$}HELP",
+        "PARAMETER",
+        "TYPE",
+        "DEFAULT",
+        name_width = name_width,
+        type_width = type_width,
+        default_width = default_width
+    );
+    println!(
+        "{}",
+        "🭶".repeat(name_width + type_width + default_width + 4)
+    );
+    for (name, kind, default, help) in rows {
+        println!(
+            "{: <name_width$}{: <type_width$}{: <default_width$}{}",
+            name,
+            kind,
+            default,
+            help,
+            name_width = name_width,
+            type_width = type_width,
+            default_width = default_width
+        );
+    }
+
+    println!();
+    println!("Parameters can be given in order or as --<name> <value> (bool: --<name>), they");
+    println!(
+        "are passed to the script as arguments and as PXC_PARAM_<NAME> environment variables."
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_param_reads_type_default_required_and_help() {
+        let param = parse_param("env:choice(dev|prod)! Target environment").unwrap();
+        assert_eq!(param.name, "env");
+        assert!(param.required);
+        assert_eq!(param.default, None);
+        assert_eq!(param.help, "Target environment");
+        match param.kind {
+            ParamType::Choice(choices) => assert_eq!(choices, ["dev", "prod"]),
+            _ => panic!("expected a choice"),
+        }
+
+        let param = parse_param("port:int=8080").unwrap();
+        assert!(matches!(param.kind, ParamType::Int));
+        assert_eq!(param.default.as_deref(), Some("8080"));
+        assert!(!param.required);
+
+        let param = parse_param("name").unwrap();
+        assert!(matches!(param.kind, ParamType::Str));
+    }
+
+    #[test]
+    fn parse_param_rejects_invalid_names_and_types() {
+        assert!(parse_param("").is_err());
+        assert!(parse_param("a.b").is_err());
+        assert!(parse_param("x:nosuchtype").is_err());
+    }
+
+    #[test]
+    fn map_args_without_params_keeps_arguments_as_given() {
+        let (cmdargs, envs) = map_args(&[], args(&["a", "--", "b", "--x"])).unwrap();
+        assert_eq!(cmdargs, ["a", "--", "b", "--x"]);
+        assert!(envs.is_empty());
+    }
+
+    #[test]
+    fn map_args_maps_named_and_positional_values() {
+        let params = vec![
+            parse_param("env!").unwrap(),
+            parse_param("port:int=8080").unwrap(),
+            parse_param("verbose:bool").unwrap(),
+        ];
+        let (cmdargs, envs) = map_args(
+            &params,
+            args(&["--verbose", "prod", "7", "x", "--", "--port"]),
+        )
+        .unwrap();
+        assert_eq!(cmdargs, ["prod", "7", "true", "x", "--port"]);
+        assert_eq!(envs[0], ("PXC_PARAM_ENV".to_string(), "prod".to_string()));
+
+        let (cmdargs, _) = map_args(&params, args(&["--port=9", "--env", "dev"])).unwrap();
+        assert_eq!(cmdargs, ["dev", "9", "false"]);
+
+        let (cmdargs, _) = map_args(&params, args(&["dev"])).unwrap();
+        assert_eq!(cmdargs, ["dev", "8080", "false"]);
+    }
+
+    #[test]
+    fn map_args_validates_values() {
+        let params = vec![parse_param("env!").unwrap(), parse_param("n:int").unwrap()];
+        assert!(map_args(&params, args(&[])).is_err());
+        assert!(map_args(&params, args(&["dev", "x"])).is_err());
+        assert!(map_args(&params, args(&["--n"])).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use super::envs::{lookup_var, EnvVar};
+use super::meta::Meta;
+use super::MapEntry;
+
+// A {{name}} or {{name:default}} placeholder in a script body
+pub struct Placeholder {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+// Split the inside of {{..}} into name and default, None if it isn't a placeholder
+// (e.g. {{.Names}} of a docker format string)
+fn parse_placeholder(inner: &str) -> Option<Placeholder> {
+    let inner = inner.trim();
+    let (name, default) = match inner.split_once(':') {
+        Some((name, default)) => (name.trim(), Some(default.to_string())),
+        None => (inner, None),
+    };
+
+    if !is_placeholder_name(name) {
+        return None;
+    }
+
+    Some(Placeholder {
+        name: name.to_string(),
+        default,
+    })
+}
+
+// Walk all {{..}} in content, calling f with the byte range and the placeholder
+fn for_each_placeholder<F>(content: &str, mut f: F)
+where
+    F: FnMut(usize, usize, Placeholder),
+{
+    let mut pos = 0;
+    while let Some(start) = content[pos..].find("{{").map(|i| pos + i) {
+        let end = match content[start + 2..].find("}}") {
+            Some(i) => start + 2 + i,
+            None => break,
+        };
+        if let Some(placeholder) = parse_placeholder(&content[start + 2..end]) {
+            f(start, end + 2, placeholder);
+        }
+        pos = end + 2;
+    }
+}
+
+// Unique placeholders in order of appearance, the first default of a name wins
+pub fn find_placeholders(content: &str) -> Vec<Placeholder> {
+    let mut placeholders: Vec<Placeholder> = Vec::new();
+    for_each_placeholder(content, |_, _, placeholder| {
+        match placeholders.iter_mut().find(|p| p.name == placeholder.name) {
+            Some(existing) => {
+                if existing.default.is_none() {
+                    existing.default = placeholder.default;
+                }
+            }
+            None => placeholders.push(placeholder),
+        }
+    });
+    placeholders
+}
+
+// Take --<name> <value> and --<name>=<value> arguments of placeholders out of args
+pub fn take_placeholder_args(
+    placeholders: &[&Placeholder],
+    args: Vec<String>,
+) -> (HashMap<String, String>, Vec<String>) {
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut rest: Vec<String> = Vec::new();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            rest.push(arg);
+            rest.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(named) = arg.strip_prefix("--") {
+            let (name, value) = match named.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (named, None),
+            };
+
+            if placeholders.iter().any(|p| p.name == name) {
+                if let Some(value) = value.or_else(|| args.next()) {
+                    values.insert(name.to_string(), value);
+                    continue;
+                }
+            }
+        }
+
+        rest.push(arg);
+    }
+
+    (values, rest)
+}
+
+fn get_env_name(name: &str) -> String {
+    name.to_uppercase().replace('-', "_")
+}
+
+fn prompt(placeholder: &Placeholder) -> Result<String, String> {
+    match &placeholder.default {
+        Some(default) => print!("{} [{}]: ", placeholder.name, default),
+        None => print!("{}: ", placeholder.name),
+    }
+    io::stdout().flush().map_err(|e| e.to_string())?;
+
+    let mut input_text = String::new();
+    io::stdin()
+        .read_line(&mut input_text)
+        .map_err(|e| format!("failed to read from stdin: {}", e))?;
+
+    let input = input_text.trim_end_matches(['\n', '\r']);
+    match (input.is_empty(), &placeholder.default) {
+        (false, _) => Ok(input.to_string()),
+        (true, Some(default)) => Ok(default.clone()),
+        (true, None) => Err(format!("no value for placeholder '{}'", placeholder.name)),
+    }
+}
+
+// Find a value for every placeholder, in order: --<name> arguments, declared parameters,
+// the environment variable <NAME> (command environment first), stored defaults (default.<name> metadata) and
+// finally an interactive prompt showing the inline default. Without a terminal the
+// inline default is used instead of prompting.
+pub fn resolve(
+    placeholders: &[Placeholder],
+    mut values: HashMap<String, String>,
+    param_values: &HashMap<String, String>,
+    command_env: &[EnvVar],
+    meta: &Meta,
+) -> Result<HashMap<String, String>, String> {
+    let interactive = io::stdin().is_terminal();
+
+    for placeholder in placeholders {
+        if values.contains_key(&placeholder.name) {
+            continue;
+        }
+
+        let value = if let Some(value) = param_values.get(&placeholder.name) {
+            value.clone()
+        } else if let Some(value) = lookup_var(command_env, &get_env_name(&placeholder.name)) {
+            value
+        } else if let Some(value) = meta.get(&format!("default.{}", placeholder.name)) {
+            value.to_string()
+        } else if interactive {
+            prompt(placeholder)?
+        } else if let Some(default) = &placeholder.default {
+            default.clone()
+        } else {
+            return Err(format!(
+                "no value for placeholder '{}', pass --{} <value>",
+                placeholder.name, placeholder.name
+            ));
+        };
+
+        values.insert(placeholder.name.clone(), value);
+    }
+
+    Ok(values)
+}
+
+pub fn render(content: &str, values: &HashMap<String, String>) -> String {
+    let mut rendered = String::new();
+    let mut pos = 0;
+    for_each_placeholder(content, |start, end, placeholder| {
+        if let Some(value) = values.get(&placeholder.name) {
+            rendered.push_str(&content[pos..start]);
+            rendered.push_str(value);
+            pos = end;
+        }
+    });
+    rendered.push_str(&content[pos..]);
+    rendered
+}
+
+// Write the rendered script to a private temporary file, the caller removes it
+pub fn write_rendered(entry: &MapEntry, rendered: &str) -> Result<PathBuf, String> {
+    let path = env::temp_dir().join(format!("pxc-{}-{}", entry.filehash, std::process::id()));
+
+    // Created with 0700 right away, the rendered script may contain entered values
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o700)
+        .open(&path)
+        .and_then(|mut file| file.write_all(rendered.as_bytes()))
+        .map_err(|e| {
+            format!(
+                "failed to write rendered script '{}': {}",
+                path.display(),
+                e
+            )
+        })?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn find_placeholders_skips_other_braces_and_keeps_first_default() {
+        let content = "echo {{ name }} {{port:80}} {{.Names}} {{1x}} {{name:x}} {{port:90}} {{open";
+        let placeholders = find_placeholders(content);
+        let found: Vec<(&str, Option<&str>)> = placeholders
+            .iter()
+            .map(|p| (p.name.as_str(), p.default.as_deref()))
+            .collect();
+        assert_eq!(found, [("name", Some("x")), ("port", Some("80"))]);
+    }
+
+    #[test]
+    fn take_placeholder_args_leaves_other_arguments() {
+        let placeholders = find_placeholders("{{env}} {{tag}}");
+        let placeholders: Vec<&Placeholder> = placeholders.iter().collect();
+        let (values, rest) = take_placeholder_args(
+            &placeholders,
+            args(&[
+                "--env", "prod", "a", "--other", "--tag=1", "--", "--env", "x",
+            ]),
+        );
+        assert_eq!(values.get("env").map(String::as_str), Some("prod"));
+        assert_eq!(values.get("tag").map(String::as_str), Some("1"));
+        assert_eq!(rest, ["a", "--other", "--", "--env", "x"]);
+    }
+
+    #[test]
+    fn render_replaces_known_placeholders_only() {
+        let mut values = HashMap::new();
+        values.insert("name".to_string(), "web".to_string());
+        assert_eq!(
+            render("{{name}}-{{ name:x }} {{other}} {{.Names}}", &values),
+            "web-web {{other}} {{.Names}}"
+        );
+    }
+}
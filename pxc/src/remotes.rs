@@ -0,0 +1,631 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+
+use super::error::Error;
+use super::layers::{Layer, PERSONAL};
+use super::namespaces;
+use super::{gen_char_sequence, get_pxc_path, read_lines, read_map_file, write_map_file};
+use super::{Config, MapEntry, SUBCOMMANDS};
+
+// remotes/remotes: one line per subscribed catalog
+//   <name>;<url>
+// and per remote the cache in remotes/<name>:
+//   fetched          the catalog index of the last pxc remote update
+//   fetched.sig      its signatures, if the catalog has any
+//   bodies/<sha256>  the scripts of the fetched and the accepted index
+//   index, index.sig the accepted catalog index and signatures
+//   map, cmd, meta   the accepted commands, a read-only store layer like ~/.pxc
+//
+// A catalog is a static directory served over HTTP: <url>/index lists the commands,
+//   <name>;<category>;<version>;<sha256>[;<description>]
+// <url>/cmd/<sha256> is the script with that SHA-256 checksum, and the optional
+// <url>/signatures has the signatures of the commands (see pxc sign)
+//   <name>;<hex public key>;<hex signature>
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_FETCH_SIZE: u64 = 16 * 1024 * 1024;
+
+pub struct Remote {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Clone, PartialEq)]
+struct CatalogEntry {
+    name: String,
+    category: String,
+    version: String,
+    sha256: String,
+    description: String,
+    // <hex public key>;<hex signature>, empty if unsigned
+    signature: String,
+}
+
+enum Change<'a> {
+    Added(&'a CatalogEntry),
+    Changed(&'a CatalogEntry, &'a CatalogEntry),
+    Removed(&'a CatalogEntry),
+}
+
+fn get_remotes_path() -> PathBuf {
+    Path::new(&get_pxc_path()).join("remotes")
+}
+
+fn get_remote_dir(name: &str) -> PathBuf {
+    get_remotes_path().join(name)
+}
+
+pub fn read_remotes() -> Vec<Remote> {
+    match read_lines(get_remotes_path().join("remotes")) {
+        Ok(lines) => lines
+            .map_while(Result::ok)
+            .filter_map(|line| {
+                let (name, url) = line.split_once(';')?;
+                Some(Remote {
+                    name: name.to_string(),
+                    url: url.to_string(),
+                })
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn save_remotes(remotes: &[Remote]) -> Result<(), Error> {
+    let remotes_path = get_remotes_path();
+    let remotes_file = remotes_path.join("remotes");
+    let content: String = remotes
+        .iter()
+        .map(|remote| format!("{};{}\n", remote.name, remote.url))
+        .collect();
+    fs::create_dir_all(&remotes_path)
+        .and_then(|_| fs::write(&remotes_file, content))
+        .map_err(|e| Error::io(format!("unable to write '{}'", remotes_file.display()), e))
+}
+
+// The accepted catalogs as store layers, below the layers of the config file
+pub fn get_layers() -> Vec<Layer> {
+    read_remotes()
+        .into_iter()
+        .map(|remote| Layer {
+            path: get_remote_dir(&remote.name).to_string_lossy().into_owned(),
+            name: remote.name,
+        })
+        .filter(|layer| Path::new(&layer.path).join("map").join("pxc").exists())
+        .collect()
+}
+
+fn get_remote<'a>(name: &str, remotes: &'a [Remote]) -> Result<&'a Remote, Error> {
+    remotes
+        .iter()
+        .find(|remote| remote.name == name)
+        .ok_or_else(|| Error::NotFound(format!("remote '{}' doesn't exist", name)))
+}
+
+// The named remote, or all of them
+fn select<'a>(name: Option<&str>, remotes: &'a [Remote]) -> Result<Vec<&'a Remote>, Error> {
+    match name {
+        Some(name) => Ok(vec![get_remote(name, remotes)?]),
+        None => Ok(remotes.iter().collect()),
+    }
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn parse_index(text: &str) -> Result<Vec<CatalogEntry>, String> {
+    let mut entries: Vec<CatalogEntry> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(5, ';').collect();
+        let valid = parts.len() >= 4
+            && parts[..3].iter().all(|part| !part.is_empty())
+            && parts[3].len() == 64
+            && parts[3].chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err(format!(
+                "index line {} is malformed: '{}'",
+                number + 1,
+                line
+            ));
+        }
+        // Names become commands and wrapper files, so they have to be valid like local ones
+        namespaces::validate_path(parts[0])
+            .and_then(|_| namespaces::validate_path(parts[1]))
+            .and_then(|_| {
+                if parts[0].starts_with('-')
+                    || parts[0].contains(char::is_whitespace)
+                    || SUBCOMMANDS.contains(&parts[0])
+                {
+                    Err(format!("'{}' is not a valid command name", parts[0]))
+                } else {
+                    Ok(())
+                }
+            })
+            .map_err(|e| format!("index line {}: {}", number + 1, e))?;
+        if entries.iter().any(|entry| entry.name == parts[0]) {
+            return Err(format!("index lists '{}' more than once", parts[0]));
+        }
+        entries.push(CatalogEntry {
+            name: parts[0].to_string(),
+            category: parts[1].to_string(),
+            version: parts[2].to_string(),
+            sha256: parts[3].to_ascii_lowercase(),
+            description: parts.get(4).unwrap_or(&"").to_string(),
+            signature: String::new(),
+        });
+    }
+    Ok(entries)
+}
+
+// The index at path with the signatures of path.sig, empty if there is none yet
+fn read_index(path: &Path) -> Result<Vec<CatalogEntry>, Error> {
+    let mut entries = match fs::read_to_string(path) {
+        Ok(text) => {
+            parse_index(&text).map_err(|e| Error::Store(format!("'{}': {}", path.display(), e)))?
+        }
+        Err(_) => return Ok(Vec::new()),
+    };
+    let signatures = fs::read_to_string(path.with_extension("sig")).unwrap_or_default();
+    for line in signatures.lines() {
+        if let Some((name, signature)) = line.trim().split_once(';') {
+            if let Some(entry) = entries.iter_mut().find(|entry| entry.name == name) {
+                entry.signature = signature.to_string();
+            }
+        }
+    }
+    Ok(entries)
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    fetch_optional(url)?.ok_or_else(|| Error::Failed(format!("'{}' doesn't exist", url)))
+}
+
+// None if the server has no such file
+fn fetch_optional(url: &str) -> Result<Option<Vec<u8>>, Error> {
+    let response = match ureq::get(url).timeout(FETCH_TIMEOUT).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Ok(None),
+        Err(e) => return Err(Error::Failed(format!("unable to fetch {}", e))),
+    };
+    let mut content = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_FETCH_SIZE)
+        .read_to_end(&mut content)
+        .map_err(|e| Error::io(format!("unable to fetch '{}'", url), e))?;
+    Ok(Some(content))
+}
+
+fn changes<'a>(accepted: &'a [CatalogEntry], fetched: &'a [CatalogEntry]) -> Vec<Change<'a>> {
+    let mut changes = Vec::new();
+    for new in fetched {
+        match accepted.iter().find(|old| old.name == new.name) {
+            None => changes.push(Change::Added(new)),
+            Some(old) if old != new => changes.push(Change::Changed(old, new)),
+            Some(_) => {}
+        }
+    }
+    for old in accepted {
+        if !fetched.iter().any(|new| new.name == old.name) {
+            changes.push(Change::Removed(old));
+        }
+    }
+    changes
+}
+
+fn summarize(changes: &[Change]) -> String {
+    let count = |f: fn(&Change) -> bool| changes.iter().filter(|change| f(change)).count();
+    format!(
+        "{} added, {} changed, {} removed",
+        count(|change| matches!(change, Change::Added(_))),
+        count(|change| matches!(change, Change::Changed(..))),
+        count(|change| matches!(change, Change::Removed(_)))
+    )
+}
+
+pub fn add(name: &str, url: &str, config: &Config) -> Result<(), Error> {
+    // remotes/remotes is the list of remotes
+    if name.is_empty() || name == "remotes" || name.contains(['/', ';']) || name.starts_with('.') {
+        return Err(Error::Usage(format!("invalid remote name '{}'", name)));
+    }
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(Error::Usage(format!(
+            "invalid url '{}', expected http:// or https://",
+            url
+        )));
+    }
+    // The remote becomes a store layer of the same name
+    if name == PERSONAL || config.layers.iter().any(|(layer, _)| layer == name) {
+        return Err(Error::Usage(format!(
+            "there is a layer named '{}' already",
+            name
+        )));
+    }
+    let mut remotes = read_remotes();
+    if remotes.iter().any(|remote| remote.name == name) {
+        return Err(Error::Usage(format!("remote '{}' exists already", name)));
+    }
+
+    remotes.push(Remote {
+        name: name.to_string(),
+        url: url.trim_end_matches('/').to_string(),
+    });
+    save_remotes(&remotes)?;
+    println!(
+        "[remote] added '{}', fetch its catalog with 'pxc remote update {}'",
+        name, name
+    );
+    Ok(())
+}
+
+pub fn remove(name: &str) -> Result<(), Error> {
+    let mut remotes = read_remotes();
+    get_remote(name, &remotes)?;
+    remotes.retain(|remote| remote.name != name);
+    save_remotes(&remotes)?;
+
+    let remote_dir = get_remote_dir(name);
+    if remote_dir.exists() {
+        fs::remove_dir_all(&remote_dir)
+            .map_err(|e| Error::io(format!("unable to remove '{}'", remote_dir.display()), e))?;
+    }
+    println!("[remote] removed '{}' and its commands", name);
+    Ok(())
+}
+
+pub fn list() -> Result<(), Error> {
+    let remotes = read_remotes();
+    if remotes.is_empty() {
+        println!("[remote] no remotes yet, add one with 'pxc remote add <name> <url>'");
+        return Ok(());
+    }
+    println!("{: <16}{: <10}{: <34}URL", "NAME", "COMMANDS", "PENDING");
+    println!("{}", "🭶".repeat(80));
+    for remote in remotes {
+        let remote_dir = get_remote_dir(&remote.name);
+        let accepted = read_index(&remote_dir.join("index"))?;
+        let pending = match fs::read_to_string(remote_dir.join("fetched")) {
+            Ok(_) => summarize(&changes(
+                &accepted,
+                &read_index(&remote_dir.join("fetched"))?,
+            )),
+            Err(_) => "not fetched".to_string(),
+        };
+        println!(
+            "{: <16}{: <10}{: <34}{}",
+            remote.name,
+            accepted.len(),
+            pending,
+            remote.url
+        );
+    }
+    Ok(())
+}
+
+// Fetch the index and the scripts it lists into the cache, nothing changes for the
+// commands until the update is accepted
+fn update_remote(remote: &Remote) -> Result<(), Error> {
+    let remote_dir = get_remote_dir(&remote.name);
+    let bodies_dir = remote_dir.join("bodies");
+    fs::create_dir_all(&bodies_dir)
+        .map_err(|e| Error::io(format!("unable to create '{}'", bodies_dir.display()), e))?;
+
+    let index = fetch(&format!("{}/index", remote.url))?;
+    let index = String::from_utf8(index)
+        .map_err(|_| Error::Failed(format!("the index of '{}' isn't text", remote.name)))?;
+    let fetched = parse_index(&index)
+        .map_err(|e| Error::Failed(format!("the index of '{}': {}", remote.name, e)))?;
+
+    let mut downloads = 0;
+    for entry in fetched.iter() {
+        let body_path = bodies_dir.join(&entry.sha256);
+        if body_path.exists() {
+            continue;
+        }
+        let body = fetch(&format!("{}/cmd/{}", remote.url, entry.sha256))?;
+        if sha256_hex(&body) != entry.sha256 {
+            return Err(Error::Failed(format!(
+                "the checksum of '{}' {} of '{}' doesn't match its script",
+                entry.name, entry.version, remote.name
+            )));
+        }
+        // Written under a temporary name, an interrupted download is no cached script
+        let tmp_path = body_path.with_extension("tmp");
+        fs::write(&tmp_path, &body)
+            .and_then(|_| fs::rename(&tmp_path, &body_path))
+            .map_err(|e| Error::io(format!("unable to write '{}'", body_path.display()), e))?;
+        downloads += 1;
+    }
+
+    // The index goes last, the previous one stays if a script couldn't be fetched
+    let fetched_path = remote_dir.join("fetched");
+    let signatures = fetch_optional(&format!("{}/signatures", remote.url))?;
+    let signatures_path = fetched_path.with_extension("sig");
+    match signatures {
+        Some(signatures) => fs::write(&signatures_path, signatures),
+        None if signatures_path.exists() => fs::remove_file(&signatures_path),
+        None => Ok(()),
+    }
+    .and_then(|_| fs::write(&fetched_path, &index))
+    .map_err(|e| Error::io(format!("unable to write '{}'", fetched_path.display()), e))?;
+    let fetched = read_index(&fetched_path)?;
+    let accepted = read_index(&remote_dir.join("index"))?;
+    remove_unused_bodies(&bodies_dir, &accepted, &fetched);
+
+    let changes = changes(&accepted, &fetched);
+    if changes.is_empty() {
+        println!("[remote] '{}' is up to date", remote.name);
+    } else {
+        println!(
+            "[remote] fetched '{}' ({} scripts downloaded): {}",
+            remote.name,
+            downloads,
+            summarize(&changes)
+        );
+        println!(
+            "[remote] review with 'pxc remote diff {}', then 'pxc remote accept {}'",
+            remote.name, remote.name
+        );
+    }
+    Ok(())
+}
+
+fn remove_unused_bodies(bodies_dir: &Path, accepted: &[CatalogEntry], fetched: &[CatalogEntry]) {
+    let bodies = match fs::read_dir(bodies_dir) {
+        Ok(bodies) => bodies,
+        Err(_) => return,
+    };
+    for body in bodies.map_while(Result::ok) {
+        let sha256 = body.file_name().to_string_lossy().into_owned();
+        if !accepted
+            .iter()
+            .chain(fetched)
+            .any(|entry| entry.sha256 == sha256)
+        {
+            let _ = fs::remove_file(body.path());
+        }
+    }
+}
+
+pub fn update(name: Option<&str>) -> Result<(), Error> {
+    let remotes = read_remotes();
+    let selected = select(name, &remotes)?;
+    let mut failed = 0;
+    for remote in selected.iter() {
+        // One unreachable catalog doesn't keep the others from updating
+        if let Err(e) = update_remote(remote) {
+            if name.is_some() {
+                return Err(e);
+            }
+            eprintln!("[remote] {}", e);
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(Error::Failed(format!(
+            "{} remotes failed to update",
+            failed
+        )));
+    }
+    Ok(())
+}
+
+fn read_body(remote_dir: &Path, entry: &CatalogEntry) -> String {
+    fs::read_to_string(remote_dir.join("bodies").join(&entry.sha256)).unwrap_or_default()
+}
+
+// The changes between the accepted and the fetched catalog, with the script diffs
+pub fn diff(name: Option<&str>) -> Result<(), Error> {
+    let remotes = read_remotes();
+    for remote in select(name, &remotes)? {
+        let remote_dir = get_remote_dir(&remote.name);
+        if !remote_dir.join("fetched").exists() {
+            println!(
+                "[remote] nothing fetched for '{}' yet, run 'pxc remote update {}'",
+                remote.name, remote.name
+            );
+            continue;
+        }
+        let accepted = read_index(&remote_dir.join("index"))?;
+        let fetched = read_index(&remote_dir.join("fetched"))?;
+        let changes = changes(&accepted, &fetched);
+        if changes.is_empty() {
+            println!("[remote] no changes for '{}'", remote.name);
+            continue;
+        }
+
+        println!("[remote] '{}': {}", remote.name, summarize(&changes));
+        for change in changes {
+            match change {
+                Change::Added(new) => {
+                    println!("+ {} {} ({})", new.name, new.version, new.category);
+                    let header = format!("{}@{}", new.name, new.version);
+                    print!(
+                        "{}",
+                        TextDiff::from_lines("", &read_body(&remote_dir, new))
+                            .unified_diff()
+                            .header("/dev/null", &header)
+                    );
+                }
+                Change::Changed(old, new) => {
+                    println!("~ {} {} -> {}", new.name, old.version, new.version);
+                    if old.category != new.category {
+                        println!("  category: {} -> {}", old.category, new.category);
+                    }
+                    if old.description != new.description {
+                        println!("  description: {} -> {}", old.description, new.description);
+                    }
+                    if old.signature != new.signature {
+                        let describe = |signature: &str| match signature.split_once(';') {
+                            Some((key, _)) => format!("key {}", key),
+                            None => "unsigned".to_string(),
+                        };
+                        println!(
+                            "  signature: {} -> {}",
+                            describe(&old.signature),
+                            describe(&new.signature)
+                        );
+                    }
+                    let old_header = format!("{}@{}", old.name, old.version);
+                    let new_header = format!("{}@{}", new.name, new.version);
+                    print!(
+                        "{}",
+                        TextDiff::from_lines(
+                            &read_body(&remote_dir, old),
+                            &read_body(&remote_dir, new)
+                        )
+                        .unified_diff()
+                        .context_radius(3)
+                        .header(&old_header, &new_header)
+                    );
+                }
+                Change::Removed(old) => println!("- {} {}", old.name, old.version),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Install the fetched catalog as the commands of the remote's layer
+fn accept_remote(remote: &Remote) -> Result<(), Error> {
+    let remote_dir = get_remote_dir(&remote.name);
+    let fetched_path = remote_dir.join("fetched");
+    if !fetched_path.exists() {
+        println!(
+            "[remote] nothing fetched for '{}' yet, run 'pxc remote update {}'",
+            remote.name, remote.name
+        );
+        return Ok(());
+    }
+    let accepted = read_index(&remote_dir.join("index"))?;
+    let fetched = read_index(&fetched_path)?;
+    let change_count = changes(&accepted, &fetched).len();
+    let map_file = remote_dir.join("map").join("pxc");
+    if change_count == 0 && map_file.exists() {
+        println!("[remote] no changes for '{}'", remote.name);
+        return Ok(());
+    }
+
+    for dir in ["map", "cmd", "meta"] {
+        fs::create_dir_all(remote_dir.join(dir)).map_err(|e| {
+            Error::io(
+                format!("unable to create '{}'", remote_dir.join(dir).display()),
+                e,
+            )
+        })?;
+    }
+    let store = remote_dir.to_string_lossy().into_owned();
+    let previous = if map_file.exists() {
+        read_map_file(&store, &remote.name)?
+    } else {
+        Vec::new()
+    };
+
+    let mut entries: Vec<MapEntry> = Vec::new();
+    for item in fetched.iter() {
+        // The filehash stays the same across versions, so the run history carries over
+        let filehash = match previous.iter().find(|entry| entry.name == item.name) {
+            Some(entry) => entry.filehash.clone(),
+            None => {
+                let mut filehash = gen_char_sequence();
+                while previous
+                    .iter()
+                    .chain(entries.iter())
+                    .any(|entry| entry.filehash == filehash)
+                {
+                    filehash = gen_char_sequence();
+                }
+                filehash
+            }
+        };
+        let mut entry = MapEntry::new(item.name.clone(), item.category.clone(), filehash);
+        entry.layer = remote.name.clone();
+        entry.store = store.clone();
+
+        // The cache is checked again, the script that runs is the one that was reviewed
+        let body_path = remote_dir.join("bodies").join(&item.sha256);
+        let body = fs::read(&body_path)
+            .map_err(|e| Error::io(format!("unable to read '{}'", body_path.display()), e))?;
+        if sha256_hex(&body) != item.sha256 {
+            return Err(Error::Store(format!(
+                "the cached script of '{}' doesn't match its checksum, run 'pxc remote update {}'",
+                item.name, remote.name
+            )));
+        }
+        let cmd_path = entry.cmd_path();
+        fs::write(&cmd_path, &body)
+            .and_then(|_| fs::set_permissions(&cmd_path, fs::Permissions::from_mode(0o700)))
+            .map_err(|e| Error::io(format!("unable to write '{}'", cmd_path.display()), e))?;
+
+        let meta_path = remote_dir.join("meta").join(&entry.filehash);
+        fs::File::create(&meta_path)
+            .and_then(|mut file| {
+                if !item.description.is_empty() {
+                    writeln!(file, "description;{}", item.description)?;
+                }
+                writeln!(file, "version;{}", item.version)?;
+                writeln!(file, "sha256;{}", item.sha256)?;
+                if !item.signature.is_empty() {
+                    writeln!(file, "signature;{}", item.signature)?;
+                }
+                writeln!(file, "remote;{}", remote.name)
+            })
+            .map_err(|e| Error::io(format!("unable to write '{}'", meta_path.display()), e))?;
+        entries.push(entry);
+    }
+
+    for entry in previous.iter() {
+        if !entries.iter().any(|other| other.filehash == entry.filehash) {
+            let _ = fs::remove_file(entry.cmd_path());
+            let _ = fs::remove_file(remote_dir.join("meta").join(&entry.filehash));
+        }
+    }
+    write_map_file(&map_file, entries.iter()).map_err(|e| {
+        Error::io(
+            format!("unable to write the map file '{}'", map_file.display()),
+            e,
+        )
+    })?;
+    let index_path = remote_dir.join("index");
+    let signatures_path = index_path.with_extension("sig");
+    let signatures_result = if fetched_path.with_extension("sig").exists() {
+        fs::copy(fetched_path.with_extension("sig"), &signatures_path).map(|_| ())
+    } else if signatures_path.exists() {
+        fs::remove_file(&signatures_path)
+    } else {
+        Ok(())
+    };
+    signatures_result
+        .and_then(|_| fs::copy(&fetched_path, &index_path))
+        .map_err(|e| Error::io(format!("unable to write '{}'", index_path.display()), e))?;
+    remove_unused_bodies(&remote_dir.join("bodies"), &fetched, &[]);
+
+    println!(
+        "[remote] accepted {} changes of '{}', {} commands",
+        change_count,
+        remote.name,
+        entries.len()
+    );
+    Ok(())
+}
+
+pub fn accept(name: Option<&str>) -> Result<(), Error> {
+    let remotes = read_remotes();
+    for remote in select(name, &remotes)? {
+        accept_remote(remote)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use std::process::ExitStatus;
+use std::thread;
+use std::time::Duration;
+
+use super::meta::Meta;
+use super::Config;
+
+const DEFAULT_KILL_AFTER: Duration = Duration::from_secs(10);
+const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+
+// Timeout and retry settings of a run, from the metadata keys timeout, kill_after,
+// retries, backoff and retry_on, overridden by --timeout and --retries
+pub struct RunPolicy {
+    pub timeout: Option<Duration>,
+    // Time between SIGTERM and SIGKILL after the timeout
+    pub kill_after: Duration,
+    pub retries: u32,
+    // Delay before the first retry, doubled for every further retry
+    pub backoff: Duration,
+    // Exit codes that are retried (and whether a timeout is), None retries every failure
+    pub retry_on: Option<Vec<i32>>,
+    pub retry_on_timeout: bool,
+}
+
+// The result of one attempt
+pub enum Outcome {
+    Exited(ExitStatus),
+    TimedOut,
+}
+
+// Parse 500ms, 30s, 5m, 2h or plain seconds
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, unit) = match value.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => value.split_at(pos),
+        None => (value, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+    match unit {
+        "ms" => Ok(Duration::from_millis(number)),
+        "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 3600)),
+        _ => Err(format!(
+            "invalid duration '{}', expected e.g. 500ms, 30s, 5m or 2h",
+            value
+        )),
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    if !millis.is_multiple_of(1000) {
+        format!("{}ms", millis)
+    } else if millis >= 3_600_000 && millis.is_multiple_of(3_600_000) {
+        format!("{}h", millis / 3_600_000)
+    } else if millis >= 60_000 && millis.is_multiple_of(60_000) {
+        format!("{}m", millis / 60_000)
+    } else {
+        format!("{}s", millis / 1000)
+    }
+}
+
+fn get_duration(meta: &Meta, key: &str) -> Result<Option<Duration>, String> {
+    match meta.get(key) {
+        Some(value) if !value.is_empty() => parse_duration(value)
+            .map(Some)
+            .map_err(|e| format!("{} ({})", e, key)),
+        _ => Ok(None),
+    }
+}
+
+pub fn get_policy(meta: &Meta, config: &Config) -> Result<RunPolicy, String> {
+    let retries = match meta.get("retries") {
+        Some(value) if !value.is_empty() => value
+            .parse::<u32>()
+            .map_err(|_| format!("invalid retry count '{}' (retries)", value))?,
+        _ => 0,
+    };
+
+    let (retry_on, retry_on_timeout) = match meta.get("retry_on") {
+        Some(value) if !value.is_empty() => {
+            let mut codes = Vec::new();
+            let mut on_timeout = false;
+            for code in value.split(',').map(str::trim) {
+                if code == "timeout" {
+                    on_timeout = true;
+                } else {
+                    codes.push(
+                        code.parse::<i32>()
+                            .map_err(|_| format!("invalid exit code '{}' (retry_on)", code))?,
+                    );
+                }
+            }
+            (Some(codes), on_timeout)
+        }
+        _ => (None, true),
+    };
+
+    Ok(RunPolicy {
+        timeout: config.timeout.or(get_duration(meta, "timeout")?),
+        kill_after: get_duration(meta, "kill_after")?.unwrap_or(DEFAULT_KILL_AFTER),
+        retries: config.retries.unwrap_or(retries),
+        backoff: get_duration(meta, "backoff")?.unwrap_or(DEFAULT_BACKOFF),
+        retry_on,
+        retry_on_timeout,
+    })
+}
+
+fn is_retryable(policy: &RunPolicy, outcome: &Outcome) -> bool {
+    match outcome {
+        Outcome::TimedOut => policy.retry_on_timeout,
+        Outcome::Exited(status) if status.success() => false,
+        Outcome::Exited(status) => match (&policy.retry_on, status.code()) {
+            (None, _) => true,
+            (Some(codes), Some(code)) => codes.contains(&code),
+            // Killed by a signal
+            (Some(_), None) => false,
+        },
+    }
+}
+
+// Run attempt until it succeeds, fails with a code that isn't retryable or the
+// retries are used up. Returns the outcome of the last attempt.
+pub fn run_with_retries<F>(
+    name: &str,
+    policy: &RunPolicy,
+    mut attempt: F,
+) -> Result<Outcome, String>
+where
+    F: FnMut() -> Result<Outcome, String>,
+{
+    let mut delay = policy.backoff;
+    let mut retry = 0;
+    loop {
+        let outcome = attempt()?;
+
+        if let Outcome::TimedOut = outcome {
+            println!(
+                "[timeout] '{}' timed out after {}",
+                name,
+                format_duration(policy.timeout.unwrap_or_default())
+            );
+        }
+
+        if retry >= policy.retries || !is_retryable(policy, &outcome) {
+            return Ok(outcome);
+        }
+
+        retry += 1;
+        println!(
+            "[retry] retrying '{}' in {} ({}/{})",
+            name,
+            format_duration(delay),
+            retry,
+            policy.retries
+        );
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+pub fn print_policy(policy: &RunPolicy) {
+    if let Some(timeout) = policy.timeout {
+        println!(
+            "{: <16}{} (SIGKILL {} after SIGTERM)",
+            "timeout",
+            format_duration(timeout),
+            format_duration(policy.kill_after)
+        );
+    }
+    if policy.retries > 0 {
+        let retry_on = match &policy.retry_on {
+            None => "any failure".to_string(),
+            Some(codes) => {
+                let mut on: Vec<String> = codes.iter().map(|c| c.to_string()).collect();
+                if policy.retry_on_timeout {
+                    on.push("timeout".to_string());
+                }
+                format!("exit codes {}", on.join(","))
+            }
+        };
+        println!(
+            "{: <16}{} (backoff {}, on {})",
+            "retries",
+            policy.retries,
+            format_duration(policy.backoff),
+            retry_on
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use similar::TextDiff;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::error::Error;
+use super::MapEntry;
+
+// rev/<filehash>/<n>: snapshots of a command script, <n> counts up from 1
+fn get_rev_dir(entry: &MapEntry) -> PathBuf {
+    Path::new(&entry.store).join("rev").join(&entry.filehash)
+}
+
+// Return the revision numbers stored for the entry, oldest first
+pub fn get_revisions(entry: &MapEntry) -> Vec<u32> {
+    let mut revisions: Vec<u32> = match fs::read_dir(get_rev_dir(entry)) {
+        Ok(dir) => dir
+            .filter_map(|file| file.ok())
+            .filter_map(|file| file.file_name().to_str()?.parse::<u32>().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    revisions.sort();
+    revisions
+}
+
+// Copy the current script into a new revision and drop revisions beyond max_revisions.
+// Empty scripts and scripts identical to the latest revision are not stored.
+pub fn snapshot(entry: &MapEntry, max_revisions: usize) {
+    if max_revisions == 0 {
+        return;
+    }
+
+    let content = match fs::read_to_string(entry.cmd_path()) {
+        Ok(content) => content,
+        Err(_) => return,
+    };
+    if content.is_empty() {
+        return;
+    }
+
+    let rev_dir = get_rev_dir(entry);
+    let mut revisions = get_revisions(entry);
+
+    if let Some(latest) = revisions.last() {
+        if let Ok(latest_content) = fs::read_to_string(rev_dir.join(latest.to_string())) {
+            if latest_content == content {
+                return;
+            }
+        }
+    }
+
+    if let Err(e) = fs::create_dir_all(&rev_dir) {
+        eprintln!(
+            "[rev] error when creating dir '{}': {}",
+            rev_dir.display(),
+            e
+        );
+        return;
+    }
+
+    let next = revisions.last().map_or(1, |latest| latest + 1);
+    if let Err(e) = fs::write(rev_dir.join(next.to_string()), content) {
+        eprintln!("[rev] failed to store revision {}: {}", next, e);
+        return;
+    }
+    revisions.push(next);
+
+    while revisions.len() > max_revisions {
+        let oldest = revisions.remove(0);
+        if let Err(e) = fs::remove_file(rev_dir.join(oldest.to_string())) {
+            eprintln!("[rev] failed to remove revision {}: {}", oldest, e);
+        }
+    }
+}
+
+pub fn remove_revisions(entry: &MapEntry) {
+    let rev_dir = get_rev_dir(entry);
+    if rev_dir.exists() {
+        if let Err(e) = fs::remove_dir_all(&rev_dir) {
+            eprintln!(
+                "[rm] Failed to remove revisions '{}': {}",
+                rev_dir.display(),
+                e
+            );
+        }
+    }
+}
+
+pub fn format_age(time: SystemTime) -> String {
+    let seconds = match SystemTime::now().duration_since(time) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => 0,
+    };
+
+    match seconds {
+        0..=59 => format!("{}s ago", seconds),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+pub fn list_revisions(entry: &MapEntry) {
+    let revisions = get_revisions(entry);
+    if revisions.is_empty() {
+        println!("[revisions] no revisions stored for '{}'", entry.name);
+        return;
+    }
+
+    let rev_dir = get_rev_dir(entry);
+
+    println!("REV\tSAVED\t\tLINES");
+    println!("{}", "🭶".repeat(30));
+    for rev in revisions.iter().rev() {
+        let rev_path = rev_dir.join(rev.to_string());
+        let saved = match fs::metadata(&rev_path).and_then(|meta| meta.modified()) {
+            Ok(modified) => format_age(modified),
+            Err(_) => "?".to_string(),
+        };
+        let lines = fs::read_to_string(&rev_path)
+            .map(|content| content.lines().count())
+            .unwrap_or(0);
+        println!("{: <8}{: <16}{}", rev, saved, lines);
+    }
+}
+
+fn read_revision(entry: &MapEntry, rev: u32) -> Option<String> {
+    fs::read_to_string(get_rev_dir(entry).join(rev.to_string())).ok()
+}
+
+// Print a unified diff from revision rev (latest if None) to the current script
+pub fn diff(entry: &MapEntry, rev: Option<u32>) -> Result<(), Error> {
+    let rev = match rev.or_else(|| get_revisions(entry).last().cloned()) {
+        Some(rev) => rev,
+        None => {
+            println!("[diff] no revisions stored for '{}'", entry.name);
+            return Ok(());
+        }
+    };
+
+    let old = read_revision(entry, rev).ok_or_else(|| {
+        Error::NotFound(format!(
+            "revision {} of '{}' doesn't exist",
+            rev, entry.name
+        ))
+    })?;
+    let new = fs::read_to_string(entry.cmd_path()).unwrap_or_default();
+
+    if old == new {
+        println!("[diff] '{}' is identical to revision {}", entry.name, rev);
+        return Ok(());
+    }
+
+    let old_header = format!("{}@{}", entry.name, rev);
+    print!(
+        "{}",
+        TextDiff::from_lines(&old, &new)
+            .unified_diff()
+            .context_radius(3)
+            .header(&old_header, &entry.name)
+    );
+    Ok(())
+}
+
+// Replace the current script with revision rev, the current script is snapshotted first
+pub fn revert(entry: &MapEntry, rev: u32, max_revisions: usize) -> Result<(), Error> {
+    let content = read_revision(entry, rev).ok_or_else(|| {
+        Error::NotFound(format!(
+            "revision {} of '{}' doesn't exist",
+            rev, entry.name
+        ))
+    })?;
+
+    snapshot(entry, max_revisions);
+
+    let cmd_path = entry.cmd_path();
+    fs::write(&cmd_path, content).map_err(|e| {
+        Error::io(
+            format!("failed to write to file '{}'", cmd_path.display()),
+            e,
+        )
+    })?;
+
+    println!("[revert] reverted '{}' to revision {}", entry.name, rev);
+    Ok(())
+}
@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::envs::{self, EnvVar};
+use super::error::{self, Error, EXIT_FAILURE, EXIT_TIMEOUT};
+use super::history;
+use super::retry::{self, Outcome, RunPolicy};
+use super::workdir::{self, WorkDir};
+use super::{composite, confirm, get_entry_by_name, meta, params, placeholders, secrets, signing};
+use super::{Config, MapEntry};
+
+// Everything needed to execute a command, see prepare_run
+pub struct PreparedRun {
+    pub cmdpath: String,
+    pub cmdargs: Vec<String>,
+    pub envs: Vec<(String, String)>,
+    pub cwd: Option<PathBuf>,
+    // Temporary working directory and rendered script, removed by cleanup_run
+    temp_cwd: Option<PathBuf>,
+    rendered_path: Option<PathBuf>,
+    // Only used to describe the run, see dry_run
+    script_path: String,
+    workdir: WorkDir,
+    command_env: Vec<EnvVar>,
+    param_envs: Vec<(String, String)>,
+    secret_vars: Vec<(String, String)>,
+    placeholder_values: Vec<(String, String)>,
+    rendered: Option<String>,
+}
+
+// Resolve parameters, environment, placeholders and working directory of a command.
+// Returns None if the declared parameters were shown because of --help.
+// A dry run has no side effects: secrets stay locked, no temporary directory is
+// created and the rendered script is not written.
+pub fn prepare_run(
+    ent: &MapEntry,
+    cmdargs: Vec<String>,
+    config: &Config,
+    dry_run: bool,
+) -> Result<Option<PreparedRun>, Error> {
+    let script_path = ent.cmd_path().to_string_lossy().into_owned();
+
+    let meta = meta::read_meta(ent);
+    let params = match params::get_params(ent, &meta) {
+        Ok(params) => params,
+        Err(e) => {
+            return Err(Error::Store(format!(
+                "invalid parameter declaration: {}",
+                e
+            )))
+        }
+    };
+
+    // Without declared parameters --help is left to the script
+    if !params.is_empty() && cmdargs.iter().any(|a| a == "--help") {
+        params::print_help(ent, &meta, &params);
+        return Ok(None);
+    }
+
+    let command_env = envs::get_command_env(config, ent, &meta).map_err(Error::Store)?;
+
+    let content = fs::read_to_string(&script_path).unwrap_or_default();
+    let all_placeholders = if meta.get("placeholders") == Some("off") {
+        Vec::new()
+    } else {
+        placeholders::find_placeholders(&content)
+    };
+
+    // Placeholders that are also declared parameters get the parameter value
+    let (placeholder_values, cmdargs) = placeholders::take_placeholder_args(
+        &all_placeholders
+            .iter()
+            .filter(|p| !params.iter().any(|param| param.name == p.name))
+            .collect::<Vec<_>>(),
+        cmdargs,
+    );
+
+    let (cmdargs, param_envs) = match params::map_args(&params, cmdargs) {
+        Ok(mapped) => mapped,
+        Err(e) => {
+            params::print_help(ent, &meta, &params);
+            return Err(Error::Usage(e));
+        }
+    };
+
+    let mut rendered = None;
+    let mut resolved_placeholders: Vec<(String, String)> = Vec::new();
+    if !all_placeholders.is_empty() {
+        let param_values: HashMap<String, String> = params
+            .iter()
+            .zip(param_envs.iter())
+            .map(|(param, (_, value))| (param.name.clone(), value.clone()))
+            .collect();
+
+        match placeholders::resolve(
+            &all_placeholders,
+            placeholder_values,
+            &param_values,
+            &command_env,
+            &meta,
+        ) {
+            Ok(values) => {
+                rendered = Some(placeholders::render(&content, &values));
+                for placeholder in all_placeholders.iter() {
+                    if let Some(value) = values.get(&placeholder.name) {
+                        resolved_placeholders.push((placeholder.name.clone(), value.clone()));
+                    }
+                }
+            }
+            Err(e) => return Err(Error::Usage(e)),
+        }
+    }
+
+    let mut envs = envs::to_pairs(&command_env);
+    envs.extend(param_envs.iter().cloned());
+
+    // Secrets only ever live in the environment of this process
+    let secret_vars = secrets::get_declared_secrets(&meta);
+    if !dry_run {
+        match secrets::get_secret_envs(config, &secret_vars) {
+            Ok(secret_envs) => envs.extend(secret_envs),
+            Err(e) => return Err(Error::Exec(e)),
+        }
+    }
+
+    let workdir = workdir::get_workdir(&meta);
+    let (cwd, cwd_is_temp) = match workdir {
+        WorkDir::Temp if dry_run => (None, false),
+        _ => match workdir::prepare(&workdir, ent, &script_path, &command_env) {
+            Ok(prepared) => prepared,
+            Err(e) => return Err(Error::Exec(e)),
+        },
+    };
+    if cwd.is_some() || cwd_is_temp {
+        if let Ok(caller_cwd) = env::current_dir() {
+            envs.push((
+                "PXC_CALLER_CWD".to_string(),
+                caller_cwd.display().to_string(),
+            ));
+        }
+    }
+
+    let mut prepared = PreparedRun {
+        cmdpath: script_path.clone(),
+        cmdargs,
+        envs,
+        temp_cwd: if cwd_is_temp { cwd.clone() } else { None },
+        cwd,
+        rendered_path: None,
+        script_path,
+        workdir,
+        command_env,
+        param_envs,
+        secret_vars,
+        placeholder_values: resolved_placeholders,
+        rendered: None,
+    };
+
+    if let Some(rendered) = rendered {
+        if !dry_run {
+            match placeholders::write_rendered(ent, &rendered) {
+                Ok(path) => {
+                    prepared.cmdpath = path.display().to_string();
+                    prepared.rendered_path = Some(path);
+                }
+                Err(e) => {
+                    cleanup_run(&prepared);
+                    return Err(Error::Exec(e));
+                }
+            }
+        }
+        prepared.rendered = Some(rendered);
+    }
+
+    Ok(Some(prepared))
+}
+
+// Remove the temporary files of a prepared run
+pub fn cleanup_run(prepared: &PreparedRun) {
+    if let Some(path) = &prepared.temp_cwd {
+        workdir::remove_temp(path);
+    }
+
+    if let Some(path) = &prepared.rendered_path {
+        if let Err(e) = fs::remove_file(path) {
+            eprintln!(
+                "[placeholders] failed to remove rendered script '{}': {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+pub fn run_cmd(arg: &str, args: Vec<String>, entries: &[MapEntry], config: &Config) -> i32 {
+    match get_entry_by_name(arg, entries) {
+        Some(ent) => run_entry(ent, args, entries, config, &mut Vec::new()),
+        None => error::report(
+            "run",
+            &Error::NotFound(format!("command '{}' not found", arg)),
+        ),
+    }
+}
+
+// Run a command (or the steps of a composite command), stack holds the composite
+// commands this run is part of. Returns the exit code of the command, errors
+// before the command ran are reported and return their exit code.
+pub fn run_entry(
+    ent: &MapEntry,
+    args: Vec<String>,
+    entries: &[MapEntry],
+    config: &Config,
+    stack: &mut Vec<String>,
+) -> i32 {
+    if let Err(e) = signing::check_run(ent, config) {
+        return error::report("verify", &e);
+    }
+    let meta = meta::read_meta(ent);
+    if !confirm::confirm_run(ent, &meta, config.assume_yes) {
+        return error::own_code(EXIT_FAILURE);
+    }
+
+    if composite::is_composite(&meta) {
+        let start = Instant::now();
+        let code = composite::run_composite(ent, args, entries, config, stack);
+        history::record(ent, code, start.elapsed());
+        return code;
+    }
+
+    let policy = match retry::get_policy(&meta, config) {
+        Ok(policy) => policy,
+        Err(e) => return error::report("retry", &Error::Store(e)),
+    };
+
+    let prepared = match prepare_run(ent, args, config, false) {
+        Ok(Some(prepared)) => prepared,
+        // --help was shown
+        Ok(None) => return 0,
+        Err(e) => return error::report("run", &e),
+    };
+
+    println!(
+        "Running command '{}' with filehash: {}",
+        ent.name, ent.filehash
+    );
+    println!("Command arguments: {}", prepared.cmdargs.join(" "));
+
+    let start = Instant::now();
+    let status = retry::run_with_retries(&ent.name, &policy, || {
+        execute_command(
+            &prepared.cmdpath,
+            &prepared.cmdargs,
+            &prepared.envs,
+            prepared.cwd.as_deref(),
+            &policy,
+        )
+    });
+
+    cleanup_run(&prepared);
+
+    let code = match status {
+        Ok(Outcome::Exited(status_code)) => {
+            if !status_code.success() {
+                eprintln!("Command execution failed with status: {}", status_code);
+            }
+            // Killed by a signal: 128 + signal like a shell
+            error::script_code(
+                status_code
+                    .code()
+                    .unwrap_or_else(|| 128 + status_code.signal().unwrap_or(0)),
+            )
+        }
+        Ok(Outcome::TimedOut) => {
+            eprintln!("Command execution failed: timed out");
+            error::own_code(EXIT_TIMEOUT)
+        }
+        Err(e) => error::report("run", &Error::Exec(format!("failed to run command: {}", e))),
+    };
+    history::record(ent, code, start.elapsed());
+    code
+}
+
+pub fn execute_command(
+    cmdpath: &str,
+    cmdargs: &[String],
+    envs: &[(String, String)],
+    cwd: Option<&Path>,
+    policy: &RunPolicy,
+) -> Result<Outcome, String> {
+    // Run through sh so scripts without a shebang still work, the arguments are
+    // passed as positional parameters and are not evaluated by the shell
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg("\"$0\" \"$@\"")
+        .arg(cmdpath)
+        .args(cmdargs)
+        .envs(envs.iter().map(|(k, v)| (k, v)));
+    if let Some(cwd) = cwd {
+        command.current_dir(cwd);
+    }
+
+    let to_error = |e| {
+        format!(
+            "Failed to execute command '{} {}': {}",
+            cmdpath,
+            cmdargs.join(" "),
+            e
+        )
+    };
+
+    let timeout = match policy.timeout {
+        Some(timeout) => timeout,
+        None => return command.status().map(Outcome::Exited).map_err(to_error),
+    };
+
+    // A process group of its own, so the timeout also stops everything the script started.
+    // On a terminal the group becomes its foreground group, so the script can read from
+    // it and gets Ctrl-C.
+    command.process_group(0);
+    let terminal = is_foreground();
+    if terminal {
+        unsafe {
+            command.pre_exec(|| {
+                libc::setpgid(0, 0);
+                set_foreground(libc::getpid());
+                Ok(())
+            });
+        }
+    }
+    let mut child = command.spawn().map_err(to_error)?;
+    if terminal {
+        set_foreground(child.id() as libc::pid_t);
+    }
+    let outcome = wait_or_stop(&mut child, timeout, policy.kill_after).map_err(to_error);
+    if terminal {
+        set_foreground(unsafe { libc::getpgrp() });
+    }
+    outcome
+}
+
+// True if pxc runs in the foreground process group of the terminal on stdin
+fn is_foreground() -> bool {
+    unsafe {
+        libc::isatty(libc::STDIN_FILENO) == 1
+            && libc::tcgetpgrp(libc::STDIN_FILENO) == libc::getpgrp()
+    }
+}
+
+// Make pgrp the foreground process group of the terminal on stdin. SIGTTOU is ignored
+// meanwhile, a process group in the background may not do that otherwise.
+fn set_foreground(pgrp: libc::pid_t) {
+    unsafe {
+        let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        libc::tcsetpgrp(libc::STDIN_FILENO, pgrp);
+        libc::signal(libc::SIGTTOU, previous);
+    }
+}
+
+// Wait for the child, after timeout its group gets SIGTERM and kill_after later SIGKILL
+fn wait_or_stop(child: &mut Child, timeout: Duration, kill_after: Duration) -> io::Result<Outcome> {
+    if let Some(status) = wait_timeout(child, timeout)? {
+        return Ok(Outcome::Exited(status));
+    }
+
+    signal_group(child, libc::SIGTERM);
+    if wait_timeout(child, kill_after)?.is_none() {
+        signal_group(child, libc::SIGKILL);
+        child.wait()?;
+    }
+    Ok(Outcome::TimedOut)
+}
+
+// Wait for the child to exit, None if it is still running after timeout
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn signal_group(child: &Child, signal: libc::c_int) {
+    // The group id is the pid of the child, see process_group(0)
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), signal);
+    }
+}
+
+// The shebang line without #!, None if the script has none or can't be read
+pub fn read_shebang(script_path: &str) -> Option<String> {
+    let content = fs::read_to_string(script_path).ok()?;
+    let shebang = content.lines().next()?.strip_prefix("#!")?;
+    Some(shebang.trim().to_string())
+}
+
+// The interpreter from the shebang line, scripts without one are run by sh
+fn get_interpreter(script_path: &str) -> String {
+    if !Path::new(script_path).exists() {
+        return "? (script file missing)".to_string();
+    }
+    read_shebang(script_path).unwrap_or_else(|| "sh (no shebang)".to_string())
+}
+
+// Quote an argument for display if the shell would split or expand it
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,@%+".contains(c))
+    {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// Print what running the command would do, without running anything
+pub fn dry_run(
+    arg: &str,
+    args: Vec<String>,
+    entries: &[MapEntry],
+    config: &Config,
+) -> Result<(), Error> {
+    let ent = get_entry_by_name(arg, entries)
+        .ok_or_else(|| Error::NotFound(format!("command '{}' not found", arg)))?;
+    println!("[dry-run] nothing is executed");
+    describe_entry(ent, args, entries, config, &mut Vec::new())
+}
+
+// Print what run_entry would do
+pub fn describe_entry(
+    ent: &MapEntry,
+    args: Vec<String>,
+    entries: &[MapEntry],
+    config: &Config,
+    stack: &mut Vec<String>,
+) -> Result<(), Error> {
+    let meta = meta::read_meta(ent);
+    if composite::is_composite(&meta) {
+        return composite::dry_run_composite(ent, args, entries, config, stack);
+    }
+
+    let prepared = match prepare_run(ent, args, config, true)? {
+        Some(prepared) => prepared,
+        None => return Ok(()),
+    };
+
+    println!("{: <16}{}", "command", ent.name);
+    println!("{: <16}{}", "category", ent.category);
+    println!("{: <16}{}", "script", prepared.script_path);
+    println!(
+        "{: <16}{}",
+        "interpreter",
+        get_interpreter(&prepared.script_path)
+    );
+
+    let mut argv = vec![prepared.script_path.clone()];
+    argv.extend(prepared.cmdargs.iter().cloned());
+    println!(
+        "{: <16}{}",
+        "argv",
+        argv.iter()
+            .map(|a| quote_arg(a))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+
+    let cwd = match (&prepared.workdir, &prepared.cwd) {
+        (WorkDir::Temp, _) => "<new temporary directory>".to_string(),
+        (_, Some(cwd)) => cwd.display().to_string(),
+        (_, None) => env::current_dir()
+            .map(|cwd| cwd.display().to_string())
+            .unwrap_or_default(),
+    };
+    println!(
+        "{: <16}{} ({})",
+        "workdir",
+        cwd,
+        workdir::format_workdir(&prepared.workdir)
+    );
+
+    if !ent.is_personal() {
+        println!("{: <16}{}", "signature", signing::check(ent).describe());
+    }
+    if confirm::is_protected(&meta) {
+        println!("{: <16}asks for confirmation", "confirm");
+    }
+    match retry::get_policy(&meta, config) {
+        Ok(policy) => retry::print_policy(&policy),
+        Err(e) => return Err(Error::Store(e)),
+    }
+
+    // Secret values are never shown, the vault isn't even unlocked
+    let mut vars = prepared.command_env;
+    for (key, value) in prepared.param_envs {
+        vars.push(EnvVar {
+            key,
+            value,
+            source: "parameter".to_string(),
+        });
+    }
+    for (key, name) in prepared.secret_vars {
+        vars.push(EnvVar {
+            key,
+            value: "********".to_string(),
+            source: format!("secret '{}'", name),
+        });
+    }
+    println!();
+    envs::print_env(&vars);
+
+    if !prepared.placeholder_values.is_empty() {
+        println!();
+        println!("PLACEHOLDERS");
+        for (name, value) in prepared.placeholder_values.iter() {
+            println!("{}={}", name, value);
+        }
+    }
+
+    if let Some(rendered) = &prepared.rendered {
+        println!();
+        println!("RENDERED SCRIPT");
+        print!("{}", rendered);
+        if !rendered.ends_with('\n') {
+            println!();
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use super::error::Error;
+use super::meta;
+use super::MapEntry;
+
+const CRONTAB_BEGIN: &str = "# BEGIN pxc schedules, generated by 'pxc schedule sync'";
+const CRONTAB_END: &str = "# END pxc schedules";
+// First line of every generated systemd unit, only these files are replaced or removed
+const UNIT_MARKER: &str = "# Generated by 'pxc schedule sync', do not edit";
+
+const MACROS: [&str; 7] = [
+    "@reboot",
+    "@yearly",
+    "@annually",
+    "@monthly",
+    "@weekly",
+    "@daily",
+    "@hourly",
+];
+
+// Check a cron expression: a macro like @daily or five fields with numbers,
+// names (jan, mon), *, ranges, lists and steps
+pub fn validate_cron(expr: &str) -> Result<(), String> {
+    if expr.starts_with('@') {
+        if MACROS.contains(&expr) {
+            return Ok(());
+        }
+        return Err(format!("unknown schedule '{}'", expr));
+    }
+
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    if fields.len() != 5 {
+        return Err(format!(
+            "'{}' needs 5 fields (minute hour day-of-month month day-of-week)",
+            expr
+        ));
+    }
+    for field in fields {
+        if !field
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "*,-/".contains(c))
+        {
+            return Err(format!("invalid field '{}' in '{}'", field, expr));
+        }
+    }
+    Ok(())
+}
+
+fn get_schedules(entries: &[MapEntry]) -> Vec<(&MapEntry, String)> {
+    let mut schedules: Vec<(&MapEntry, String)> = entries
+        .iter()
+        .filter_map(|entry| {
+            meta::read_meta(entry)
+                .get("schedule")
+                .filter(|schedule| !schedule.is_empty())
+                .map(|schedule| (entry, schedule.to_string()))
+        })
+        .collect();
+    schedules.sort_by(|a, b| a.0.name.cmp(&b.0.name));
+    schedules
+}
+
+pub fn set_schedule(entry: &MapEntry, expr: &str) -> Result<(), Error> {
+    validate_cron(expr).map_err(Error::Usage)?;
+
+    let mut entry_meta = meta::read_meta(entry);
+    entry_meta.set("schedule", expr);
+    meta::save_meta(entry, &entry_meta)?;
+    println!(
+        "[schedule] '{}' runs at '{}', run 'pxc schedule sync' to install it",
+        entry.name, expr
+    );
+    Ok(())
+}
+
+pub fn remove_schedule(entry: &MapEntry) -> Result<(), Error> {
+    let mut entry_meta = meta::read_meta(entry);
+    if entry_meta.get("schedule").is_none() {
+        return Err(Error::NotFound(format!("'{}' isn't scheduled", entry.name)));
+    }
+    entry_meta.remove("schedule");
+    meta::save_meta(entry, &entry_meta)?;
+    println!(
+        "[schedule] removed the schedule of '{}', run 'pxc schedule sync' to uninstall it",
+        entry.name
+    );
+    Ok(())
+}
+
+pub fn list_schedules(entries: &[MapEntry]) {
+    println!("NAME\t\tSCHEDULE");
+    println!("{}", "🭶".repeat(30));
+    for (entry, schedule) in get_schedules(entries) {
+        println!("{: <16}{}", entry.name, schedule);
+    }
+}
+
+fn get_pxc_exe() -> String {
+    env::current_exe()
+        .map(|exe| exe.display().to_string())
+        .unwrap_or("pxc".to_string())
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+// Scheduled runs have no terminal, so protected commands run with --yes
+fn get_command_line(entry: &MapEntry) -> String {
+    format!(
+        "{} --yes {}",
+        shell_quote(&get_pxc_exe()),
+        shell_quote(&entry.name)
+    )
+}
+
+fn sync_crontab(entries: &[MapEntry]) -> Result<(), String> {
+    // No crontab yet is not an error
+    let current = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run crontab: {}", e))?;
+    let current = String::from_utf8_lossy(&current.stdout).to_string();
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut in_block = false;
+    for line in current.lines() {
+        if line == CRONTAB_BEGIN {
+            in_block = true;
+        } else if line == CRONTAB_END {
+            in_block = false;
+        } else if !in_block {
+            lines.push(line.to_string());
+        }
+    }
+
+    let schedules = get_schedules(entries);
+    if !schedules.is_empty() {
+        lines.push(CRONTAB_BEGIN.to_string());
+        for (entry, schedule) in schedules.iter() {
+            // % starts stdin in crontab lines
+            lines.push(format!(
+                "{} {}",
+                schedule,
+                get_command_line(entry).replace('%', "\\%")
+            ));
+        }
+        lines.push(CRONTAB_END.to_string());
+    }
+
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run crontab: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        let mut content = lines.join("\n");
+        content.push('\n');
+        stdin
+            .write_all(content.as_bytes())
+            .map_err(|e| format!("failed to write the crontab: {}", e))?;
+    }
+    match child.wait() {
+        Ok(status) if status.success() => {
+            println!(
+                "[schedule] crontab updated, {} scheduled command(s)",
+                schedules.len()
+            );
+            Ok(())
+        }
+        _ => Err("crontab rejected the new entries".to_string()),
+    }
+}
+
+fn get_unit_dir() -> Result<PathBuf, String> {
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match home::home_dir() {
+            Some(home) => home.join(".config"),
+            None => return Err("unable to find the home directory".to_string()),
+        },
+    };
+    Ok(config_dir.join("systemd").join("user"))
+}
+
+// Escaped like systemd-escape so that different names never share a unit: / becomes -,
+// anything but ASCII letters, digits, _ and a . that doesn't lead becomes \xNN
+fn get_unit_name(entry: &MapEntry) -> String {
+    let mut name = String::new();
+    for (i, byte) in entry.name.bytes().enumerate() {
+        match byte {
+            b'/' => name.push('-'),
+            b'.' if i > 0 => name.push('.'),
+            b'_' => name.push('_'),
+            byte if byte.is_ascii_alphanumeric() => name.push(byte as char),
+            byte => name.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    format!("pxc-{}", name)
+}
+
+const WEEKDAYS: [&str; 8] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+fn convert_weekday(day: &str) -> String {
+    match day.parse::<usize>() {
+        Ok(n) if n < WEEKDAYS.len() => WEEKDAYS[n].to_string(),
+        _ => {
+            // Names like mon are kept, systemd wants Mon
+            let mut chars = day.chars();
+            match chars.next() {
+                Some(c) => c.to_ascii_uppercase().to_string() + &chars.as_str().to_lowercase(),
+                None => String::new(),
+            }
+        }
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+// One cron field to systemd calendar syntax: ranges a-b become a..b, */n becomes first/n
+fn convert_field(field: &str, first: u32, convert: &dyn Fn(&str) -> String) -> String {
+    field
+        .split(',')
+        .map(|part| {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (range, Some(step)),
+                None => (part, None),
+            };
+            let range = match range {
+                "*" if step.is_some() => first.to_string(),
+                "*" => "*".to_string(),
+                _ => match range.split_once('-') {
+                    Some((from, to)) => format!("{}..{}", convert(from), convert(to)),
+                    None => convert(range),
+                },
+            };
+            match step {
+                Some(step) => format!("{}/{}", range, step),
+                None => range,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// The [Timer] lines of a cron expression
+fn cron_to_timer(expr: &str) -> Result<String, String> {
+    match expr {
+        "@reboot" => return Ok("OnBootSec=1min".to_string()),
+        "@annually" => return Ok("OnCalendar=yearly\nPersistent=true".to_string()),
+        _ => {}
+    }
+    if let Some(period) = expr.strip_prefix('@') {
+        return Ok(format!("OnCalendar={}\nPersistent=true", period));
+    }
+
+    let fields: Vec<&str> = expr.split_whitespace().collect();
+    let keep = |value: &str| value.to_string();
+    let month = |value: &str| match MONTHS.iter().position(|m| value.eq_ignore_ascii_case(m)) {
+        Some(i) => (i + 1).to_string(),
+        None => value.to_string(),
+    };
+
+    if fields[2] != "*" && fields[4] != "*" {
+        return Err(format!(
+            "'{}' restricts day of month and day of week, cron runs on either while systemd needs both",
+            expr
+        ));
+    }
+
+    let minute = convert_field(fields[0], 0, &keep);
+    let hour = convert_field(fields[1], 0, &keep);
+    let day = convert_field(fields[2], 1, &keep);
+    let month = convert_field(fields[3], 1, &month);
+    let weekday = if fields[4] == "*" {
+        String::new()
+    } else {
+        format!("{} ", convert_field(fields[4], 0, &convert_weekday))
+    };
+
+    Ok(format!(
+        "OnCalendar={}*-{}-{} {}:{}:00\nPersistent=true",
+        weekday, month, day, hour, minute
+    ))
+}
+
+fn systemctl(args: &[&str]) -> bool {
+    Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn sync_systemd(entries: &[MapEntry]) -> Result<(), String> {
+    let unit_dir = get_unit_dir()?;
+    fs::create_dir_all(&unit_dir)
+        .map_err(|e| format!("unable to create '{}': {}", unit_dir.display(), e))?;
+
+    let schedules = get_schedules(entries);
+    let mut timers: Vec<String> = Vec::new();
+    for (entry, schedule) in schedules.iter() {
+        let unit = get_unit_name(entry);
+        let timer = cron_to_timer(schedule).map_err(|e| format!("'{}': {}", entry.name, e))?;
+
+        let service = format!(
+            "{}\n[Unit]\nDescription=pxc {}\n\n[Service]\nType=oneshot\nExecStart={}\n",
+            UNIT_MARKER,
+            entry.name,
+            get_command_line(entry)
+        );
+        let timer = format!(
+            "{}\n[Unit]\nDescription=Schedule of pxc {} ({})\n\n[Timer]\n{}\n\n[Install]\nWantedBy=timers.target\n",
+            UNIT_MARKER, entry.name, schedule, timer
+        );
+
+        for (extension, content) in [("service", service), ("timer", timer)] {
+            let path = unit_dir.join(format!("{}.{}", unit, extension));
+            fs::write(&path, content)
+                .map_err(|e| format!("unable to write '{}': {}", path.display(), e))?;
+        }
+        timers.push(format!("{}.timer", unit));
+    }
+
+    // Remove the units of commands that aren't scheduled anymore
+    let mut removed: Vec<String> = Vec::new();
+    if let Ok(dir) = fs::read_dir(&unit_dir) {
+        for file in dir.map_while(Result::ok) {
+            let file_name = file.file_name().to_string_lossy().to_string();
+            if !file_name.starts_with("pxc-") {
+                continue;
+            }
+            let generated = fs::read_to_string(file.path())
+                .is_ok_and(|content| content.starts_with(UNIT_MARKER));
+            let unit = file_name.rsplit_once('.').map_or("", |(unit, _)| unit);
+            if generated && !timers.contains(&format!("{}.timer", unit)) {
+                if file_name.ends_with(".timer") {
+                    systemctl(&["disable", "--now", &file_name]);
+                }
+                let _ = fs::remove_file(file.path());
+                removed.push(file_name);
+            }
+        }
+    }
+
+    let mut activated = systemctl(&["daemon-reload"]);
+    for timer in timers.iter() {
+        activated = activated && systemctl(&["enable", "--now", timer]);
+    }
+
+    println!(
+        "[schedule] wrote {} timer(s) to '{}', removed {} unit file(s)",
+        timers.len(),
+        unit_dir.display(),
+        removed.len()
+    );
+    if !activated {
+        eprintln!("[schedule] unable to activate the timers, run 'systemctl --user daemon-reload' and 'systemctl --user enable --now <timer>'");
+    }
+    Ok(())
+}
+
+pub fn sync(entries: &[MapEntry], target: &str) -> Result<(), Error> {
+    match target {
+        "crontab" => sync_crontab(entries).map_err(Error::Failed),
+        "systemd-user" => sync_systemd(entries).map_err(Error::Failed),
+        _ => Err(Error::Usage(format!(
+            "unknown target '{}', expected crontab or systemd-user",
+            target
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_name(name: &str) -> String {
+        get_unit_name(&MapEntry::new(
+            name.to_string(),
+            "cat".to_string(),
+            "AAAAAAAA".to_string(),
+        ))
+    }
+
+    #[test]
+    fn validate_cron_checks_fields_and_macros() {
+        assert!(validate_cron("*/5 1-3 * jan-mar mon,fri").is_ok());
+        assert!(validate_cron("@daily").is_ok());
+        assert!(validate_cron("@often").is_err());
+        assert!(validate_cron("* * * *").is_err());
+        assert!(validate_cron("* * * * $(x)").is_err());
+    }
+
+    #[test]
+    fn cron_to_timer_converts_fields() {
+        assert_eq!(
+            cron_to_timer("30 2 * * *").unwrap(),
+            "OnCalendar=*-*-* 2:30:00\nPersistent=true"
+        );
+        assert_eq!(
+            cron_to_timer("*/15 8-18 * * 1-5").unwrap(),
+            "OnCalendar=Mon..Fri *-*-* 8..18:0/15:00\nPersistent=true"
+        );
+        assert_eq!(
+            cron_to_timer("0 0 1 jan,jul *").unwrap(),
+            "OnCalendar=*-1,7-1 0:0:00\nPersistent=true"
+        );
+        assert_eq!(
+            cron_to_timer("0 9 * * sun").unwrap(),
+            "OnCalendar=Sun *-*-* 9:0:00\nPersistent=true"
+        );
+        assert_eq!(
+            cron_to_timer("@weekly").unwrap(),
+            "OnCalendar=weekly\nPersistent=true"
+        );
+        assert_eq!(cron_to_timer("@reboot").unwrap(), "OnBootSec=1min");
+        assert!(cron_to_timer("0 0 1 * mon").is_err());
+    }
+
+    #[test]
+    fn unit_names_are_distinct_for_distinct_names() {
+        assert_eq!(unit_name("backup"), "pxc-backup");
+        assert_eq!(unit_name("a/b"), "pxc-a-b");
+        assert_eq!(unit_name("a-b"), "pxc-a\\x2db");
+        assert_eq!(unit_name("a_b.sh"), "pxc-a_b.sh");
+        assert_eq!(unit_name(".hidden"), "pxc-\\x2ehidden");
+        assert_ne!(unit_name("a/b"), unit_name("a-b"));
+        assert_ne!(unit_name("a b"), unit_name("a_b"));
+    }
+}
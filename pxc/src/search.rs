@@ -0,0 +1,352 @@
+use regex::RegexBuilder;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::error::Error;
+use super::meta::{self, get_meta_path};
+use super::output::{self, Format, Value};
+use super::{get_pxc_path, read_lines, MapEntry};
+
+// Inverted index over the scripts, names and metadata, stored in index/index as
+//   doc;<filehash>;<signature>
+//   term;<word>;<filehash>:<count>,<filehash>:<count>..
+#[derive(Default)]
+struct Index {
+    docs: HashMap<String, String>,
+    terms: BTreeMap<String, HashMap<String, usize>>,
+}
+
+fn get_index_path() -> PathBuf {
+    Path::new(&get_pxc_path()).join("index").join("index")
+}
+
+fn read_index() -> Index {
+    let mut index = Index::default();
+    if let Ok(lines) = read_lines(get_index_path()) {
+        for line in lines.map_while(Result::ok) {
+            let parts: Vec<&str> = line.splitn(3, ';').collect();
+            match parts[..] {
+                ["doc", filehash, signature] => {
+                    index
+                        .docs
+                        .insert(filehash.to_string(), signature.to_string());
+                }
+                ["term", term, postings] => {
+                    let postings = postings
+                        .split(',')
+                        .filter_map(|posting| posting.split_once(':'))
+                        .filter_map(|(filehash, count)| {
+                            count
+                                .parse()
+                                .ok()
+                                .map(|count| (filehash.to_string(), count))
+                        })
+                        .collect();
+                    index.terms.insert(term.to_string(), postings);
+                }
+                // A broken line only costs a reindex of the commands it described
+                _ => {}
+            }
+        }
+    }
+    index
+}
+
+fn save_index(index: &Index) {
+    let index_path = get_index_path();
+    if let Some(index_dir) = index_path.parent() {
+        if let Err(e) = fs::create_dir_all(index_dir) {
+            eprintln!("[search] error when creating dir: {}", e);
+            return;
+        }
+    }
+
+    if let Err(e) = fs::File::create(&index_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        for (filehash, signature) in index.docs.iter() {
+            writeln!(writer, "doc;{};{}", filehash, signature)?;
+        }
+        for (term, postings) in index.terms.iter() {
+            let postings: Vec<String> = postings
+                .iter()
+                .map(|(filehash, count)| format!("{}:{}", filehash, count))
+                .collect();
+            writeln!(writer, "term;{};{}", term, postings.join(","))?;
+        }
+        writer.flush()
+    }) {
+        eprintln!(
+            "[search] failed to write to file '{}': {}",
+            index_path.display(),
+            e
+        );
+    }
+}
+
+// Changes whenever the script, the metadata or the names of a command change
+fn get_signature(entry: &MapEntry) -> String {
+    let mut hasher = DefaultHasher::new();
+    for path in [entry.cmd_path(), get_meta_path(entry)] {
+        if let Ok(metadata) = fs::metadata(path) {
+            metadata.len().hash(&mut hasher);
+            if let Ok(modified) = metadata.modified() {
+                modified
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_nanos()
+                    .hash(&mut hasher);
+            }
+        }
+    }
+    entry.name.hash(&mut hasher);
+    entry.category.hash(&mut hasher);
+    entry.aliases.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+// Lowercase words of letters, digits and underscores
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+fn read_terms(entry: &MapEntry) -> HashMap<String, usize> {
+    let mut text = format!(
+        "{} {} {}\n",
+        entry.name,
+        entry.category,
+        entry.aliases.join(" ")
+    );
+    if let Ok(script) = fs::read(entry.cmd_path()) {
+        text.push_str(&String::from_utf8_lossy(&script));
+    }
+    for (_, value) in meta::read_meta(entry).get_entries() {
+        text.push('\n');
+        text.push_str(value);
+    }
+
+    let mut terms = HashMap::new();
+    for term in tokenize(&text) {
+        *terms.entry(term).or_insert(0) += 1;
+    }
+    terms
+}
+
+// Drop the terms of the given commands in one pass over the index
+fn remove_documents(index: &mut Index, filehashes: &HashSet<String>) {
+    if filehashes.is_empty() {
+        return;
+    }
+    index
+        .docs
+        .retain(|filehash, _| !filehashes.contains(filehash));
+    for postings in index.terms.values_mut() {
+        postings.retain(|filehash, _| !filehashes.contains(filehash));
+    }
+    index.terms.retain(|_, postings| !postings.is_empty());
+}
+
+// Read the commands whose files changed since they were indexed and drop removed
+// ones, returns true if the index changed
+fn refresh(index: &mut Index, entries: &[MapEntry]) -> bool {
+    let current: HashSet<&str> = entries.iter().map(|entry| &entry.filehash[..]).collect();
+    let mut stale: HashSet<String> = index
+        .docs
+        .keys()
+        .filter(|filehash| !current.contains(&filehash[..]))
+        .cloned()
+        .collect();
+
+    let mut changed: Vec<(&MapEntry, String)> = Vec::new();
+    for entry in entries {
+        let signature = get_signature(entry);
+        match index.docs.get(&entry.filehash) {
+            Some(indexed) if *indexed == signature => {}
+            Some(_) => {
+                stale.insert(entry.filehash.clone());
+                changed.push((entry, signature));
+            }
+            None => changed.push((entry, signature)),
+        }
+    }
+    if stale.is_empty() && changed.is_empty() {
+        return false;
+    }
+
+    remove_documents(index, &stale);
+    for (entry, signature) in changed {
+        for (term, count) in read_terms(entry) {
+            index
+                .terms
+                .entry(term)
+                .or_default()
+                .insert(entry.filehash.clone(), count);
+        }
+        index.docs.insert(entry.filehash.clone(), signature);
+    }
+    true
+}
+
+// Called whenever the map is saved, so add, edit, rm and alias keep the index current
+pub fn update_index(entries: &[MapEntry]) {
+    let mut index = read_index();
+    if refresh(&mut index, entries) {
+        save_index(&index);
+    }
+}
+
+pub fn reindex(entries: &[MapEntry]) {
+    let mut index = Index::default();
+    refresh(&mut index, entries);
+    save_index(&index);
+    println!("[search] indexed {} commands", index.docs.len());
+}
+
+// Commands containing all words, a word also matches longer words starting with it.
+// Ranked by how often the words occur.
+pub fn search(words: &[String], entries: &[MapEntry], format: Format) {
+    // Scripts may have been changed outside of pxc
+    let mut index = read_index();
+    if refresh(&mut index, entries) {
+        save_index(&index);
+    }
+
+    let mut scores: Option<HashMap<String, usize>> = None;
+    for word in words.iter().flat_map(|word| tokenize(word)) {
+        let mut matches: HashMap<String, usize> = HashMap::new();
+        for (_, postings) in index
+            .terms
+            .range(word.clone()..)
+            .take_while(|(term, _)| term.starts_with(&word))
+        {
+            for (filehash, count) in postings {
+                *matches.entry(filehash.clone()).or_insert(0) += count;
+            }
+        }
+        scores = Some(match scores {
+            None => matches,
+            Some(scores) => scores
+                .into_iter()
+                .filter_map(|(filehash, score)| {
+                    matches
+                        .get(&filehash)
+                        .map(|count| (filehash, score + count))
+                })
+                .collect(),
+        });
+    }
+    let scores = scores.unwrap_or_default();
+
+    let mut results: Vec<(&MapEntry, usize)> = entries
+        .iter()
+        .filter_map(|entry| scores.get(&entry.filehash).map(|score| (entry, *score)))
+        .collect();
+    results.sort_by(|(a, a_score), (b, b_score)| b_score.cmp(a_score).then(a.name.cmp(&b.name)));
+
+    if format != Format::Table {
+        let rows: Vec<Vec<Value>> = results
+            .iter()
+            .map(|(entry, score)| {
+                vec![
+                    Value::Str(entry.name.clone()),
+                    Value::Str(entry.category.clone()),
+                    Value::opt_str(meta::read_meta(entry).get("description")),
+                    Value::Int(*score as i64),
+                ]
+            })
+            .collect();
+        output::print_rows(format, &["name", "category", "description", "score"], &rows);
+        return;
+    }
+
+    if results.is_empty() {
+        println!("[search] no command matches '{}'", words.join(" "));
+        return;
+    }
+
+    let width = results
+        .iter()
+        .map(|(entry, _)| entry.name.len().max(entry.category.len()) + 1)
+        .fold(16, usize::max);
+    println!(
+        "{: <width$}{: <width$}DESCRIPTION",
+        "NAME",
+        "CATEGORY",
+        width = width
+    );
+    println!("{}", "🭶".repeat(width * 2 + 11));
+    for (entry, _) in results {
+        println!(
+            "{: <width$}{: <width$}{}",
+            entry.name,
+            entry.category,
+            meta::read_meta(entry).get("description").unwrap_or(""),
+            width = width
+        );
+    }
+}
+
+// Print the lines of all scripts matching the regex, like grep with -C context
+pub fn grep(
+    pattern: &str,
+    context: usize,
+    ignore_case: bool,
+    entries: &[MapEntry],
+) -> Result<(), Error> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| Error::Usage(format!("invalid pattern: {}", e)))?;
+
+    let mut sorted: Vec<&MapEntry> = entries.iter().collect();
+    sorted.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut first_group = true;
+    let mut found = false;
+    for entry in sorted {
+        let script = match fs::read(entry.cmd_path()) {
+            Ok(script) => String::from_utf8_lossy(&script).into_owned(),
+            Err(_) => continue,
+        };
+        let lines: Vec<&str> = script.lines().collect();
+
+        // Last line printed so far, overlapping context is printed once
+        let mut printed_until: Option<usize> = None;
+        for (i, line) in lines.iter().enumerate() {
+            if !regex.is_match(line) {
+                continue;
+            }
+            found = true;
+
+            let start = i.saturating_sub(context);
+            let start = match printed_until {
+                Some(until) if until + 1 >= start => until + 1,
+                _ => {
+                    if !first_group {
+                        println!("--");
+                    }
+                    first_group = false;
+                    start
+                }
+            };
+            let end = (i + context).min(lines.len() - 1);
+            for (j, line) in lines.iter().enumerate().take(end + 1).skip(start) {
+                let separator = if regex.is_match(line) { ':' } else { '-' };
+                println!("{}{}{}{}{}", entry.name, separator, j + 1, separator, line);
+            }
+            printed_until = Some(printed_until.map_or(end, |until| until.max(end)));
+        }
+    }
+
+    if !found {
+        println!("[grep] no script matches '{}'", pattern);
+    }
+    Ok(())
+}
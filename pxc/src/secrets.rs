@@ -0,0 +1,301 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use super::error::Error;
+use super::meta::{self, Meta};
+use super::{get_pxc_path, Config, MapEntry};
+
+// secrets/vault: MAGIC, salt, nonce, then the encrypted name;hex(value) lines
+const MAGIC: &[u8] = b"PXCVAULT1";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+// Passphrase for non-interactive use (cron, CI) when no key file is configured
+const PASSPHRASE_VAR: &str = "PXC_SECRETS_PASSPHRASE";
+
+pub struct Vault {
+    salt: [u8; SALT_LEN],
+    key: Key,
+    secrets: BTreeMap<String, String>,
+}
+
+fn get_vault_path() -> PathBuf {
+    Path::new(&get_pxc_path()).join("secrets").join("vault")
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Write a file only readable by the owner
+pub fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    // Only a directory created here is made private, existing ones are left alone
+    if let Some(dir) = path.parent().filter(|dir| !dir.exists()) {
+        fs::create_dir_all(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+// The key material: the configured key file (created on first use), otherwise the
+// passphrase from PXC_SECRETS_PASSPHRASE or a prompt
+fn get_key_material(config: &Config, create: bool) -> Result<Vec<u8>, String> {
+    if !config.secrets_keyfile.is_empty() {
+        // Relative to the pxc directory and inside it
+        let relative = Path::new(&config.secrets_keyfile);
+        if !relative
+            .components()
+            .all(|part| matches!(part, Component::Normal(_)))
+        {
+            return Err(format!(
+                "secrets_keyfile '{}' has to be a relative path inside '{}'",
+                config.secrets_keyfile,
+                get_pxc_path()
+            ));
+        }
+        let keyfile = &Path::new(&get_pxc_path()).join(relative);
+        if !keyfile.exists() && create {
+            let mut material = [0u8; 32];
+            OsRng.fill_bytes(&mut material);
+            write_private(keyfile, &material)
+                .map_err(|e| format!("unable to create key file '{}': {}", keyfile.display(), e))?;
+            println!("[secret] created key file '{}'", keyfile.display());
+        }
+        return fs::read(keyfile)
+            .map_err(|e| format!("unable to read key file '{}': {}", keyfile.display(), e));
+    }
+
+    if let Ok(passphrase) = env::var(PASSPHRASE_VAR) {
+        return Ok(passphrase.into_bytes());
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(format!(
+            "no key file configured and {} isn't set",
+            PASSPHRASE_VAR
+        ));
+    }
+
+    let passphrase = rpassword::prompt_password("vault passphrase: ")
+        .map_err(|e| format!("failed to read passphrase: {}", e))?;
+    if create && !get_vault_path().exists() {
+        let repeated = rpassword::prompt_password("repeat passphrase: ")
+            .map_err(|e| format!("failed to read passphrase: {}", e))?;
+        if repeated != passphrase {
+            return Err("passphrases don't match".to_string());
+        }
+    }
+    Ok(passphrase.into_bytes())
+}
+
+fn derive_key(material: &[u8], salt: &[u8]) -> Result<Key, String> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(material, salt, &mut key)
+        .map_err(|e| format!("key derivation failed: {}", e))?;
+    Ok(Key::from(key))
+}
+
+// Open the vault, a missing vault is created (empty) if create is set
+pub fn open_vault(config: &Config, create: bool) -> Result<Vault, String> {
+    let vault_path = get_vault_path();
+
+    if !vault_path.exists() {
+        if !create {
+            return Err("no secrets stored yet, see 'pxc secret set'".to_string());
+        }
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let key = derive_key(&get_key_material(config, true)?, &salt)?;
+        return Ok(Vault {
+            salt,
+            key,
+            secrets: BTreeMap::new(),
+        });
+    }
+
+    let data = fs::read(&vault_path)
+        .map_err(|e| format!("unable to read vault '{}': {}", vault_path.display(), e))?;
+    if data.len() < MAGIC.len() + SALT_LEN + NONCE_LEN || !data.starts_with(MAGIC) {
+        return Err(format!("'{}' is not a pxc vault", vault_path.display()));
+    }
+
+    let mut salt = [0u8; SALT_LEN];
+    salt.copy_from_slice(&data[MAGIC.len()..MAGIC.len() + SALT_LEN]);
+    let nonce =
+        Nonce::from_slice(&data[MAGIC.len() + SALT_LEN..MAGIC.len() + SALT_LEN + NONCE_LEN]);
+    let key = derive_key(&get_key_material(config, false)?, &salt)?;
+
+    let plaintext = ChaCha20Poly1305::new(&key)
+        .decrypt(nonce, &data[MAGIC.len() + SALT_LEN + NONCE_LEN..])
+        .map_err(|_| "unable to decrypt the vault, wrong passphrase or key file".to_string())?;
+
+    let mut secrets = BTreeMap::new();
+    for line in String::from_utf8_lossy(&plaintext).lines() {
+        if let Some((name, hex)) = line.split_once(';') {
+            if let Some(value) = from_hex(hex) {
+                secrets.insert(
+                    name.to_string(),
+                    String::from_utf8_lossy(&value).to_string(),
+                );
+            }
+        }
+    }
+
+    Ok(Vault { salt, key, secrets })
+}
+
+fn save_vault(vault: &Vault) -> Result<(), String> {
+    let mut plaintext = String::new();
+    for (name, value) in vault.secrets.iter() {
+        plaintext.push_str(&format!("{};{}\n", name, to_hex(value.as_bytes())));
+    }
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = ChaCha20Poly1305::new(&vault.key)
+        .encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| "unable to encrypt the vault".to_string())?;
+
+    let mut data = MAGIC.to_vec();
+    data.extend_from_slice(&vault.salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+
+    let vault_path = get_vault_path();
+    write_private(&vault_path, &data)
+        .map_err(|e| format!("unable to write vault '{}': {}", vault_path.display(), e))
+}
+
+fn is_secret_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+// Read the value without echo from a terminal, or from stdin when piped.
+// Values are never taken from the command line to keep them out of shell history.
+fn read_secret_value(name: &str) -> Result<String, String> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password(format!("value of '{}': ", name))
+            .map_err(|e| format!("failed to read value: {}", e));
+    }
+
+    let mut value = String::new();
+    io::stdin()
+        .read_to_string(&mut value)
+        .map_err(|e| format!("failed to read from stdin: {}", e))?;
+    Ok(value.trim_end_matches(['\n', '\r']).to_string())
+}
+
+pub fn set_secret(config: &Config, name: &str) -> Result<(), Error> {
+    if !is_secret_name(name) {
+        return Err(Error::Usage(format!("invalid secret name '{}'", name)));
+    }
+
+    let mut vault = open_vault(config, true).map_err(Error::Failed)?;
+    let value = read_secret_value(name).map_err(Error::Failed)?;
+
+    vault.secrets.insert(name.to_string(), value);
+    save_vault(&vault).map_err(Error::Failed)?;
+    println!("[secret] stored '{}'", name);
+    Ok(())
+}
+
+pub fn get_secret(config: &Config, name: &str) -> Result<(), Error> {
+    let vault = open_vault(config, false).map_err(Error::Failed)?;
+    match vault.secrets.get(name) {
+        Some(value) => println!("{}", value),
+        None => return Err(Error::NotFound(format!("secret '{}' doesn't exist", name))),
+    }
+    Ok(())
+}
+
+pub fn remove_secret(config: &Config, name: &str) -> Result<(), Error> {
+    let mut vault = open_vault(config, false).map_err(Error::Failed)?;
+    if vault.secrets.remove(name).is_none() {
+        return Err(Error::NotFound(format!("secret '{}' doesn't exist", name)));
+    }
+
+    save_vault(&vault).map_err(Error::Failed)?;
+    println!("[secret] removed '{}'", name);
+    Ok(())
+}
+
+pub fn list_secrets(config: &Config, entries: &[MapEntry]) -> Result<(), Error> {
+    let vault = open_vault(config, false).map_err(Error::Failed)?;
+
+    println!("SECRET\t\tUSED BY");
+    println!("{}", "🭶".repeat(30));
+    for name in vault.secrets.keys() {
+        let used_by: Vec<&str> = entries
+            .iter()
+            .filter(|entry| {
+                get_declared_secrets(&meta::read_meta(entry))
+                    .iter()
+                    .any(|(_, secret)| secret == name)
+            })
+            .map(|entry| entry.name.as_str())
+            .collect();
+        println!("{: <16}{}", name, used_by.join(","));
+    }
+    Ok(())
+}
+
+// Secrets a command declares: secret;<name> exports <name>, secret;<VAR>=<name>
+// exports the secret <name> as <VAR>. Returns (variable, secret name) pairs.
+pub fn get_declared_secrets(meta: &Meta) -> Vec<(String, String)> {
+    meta.get_all("secret")
+        .iter()
+        .map(|value| match value.split_once('=') {
+            Some((var, name)) => (var.trim().to_string(), name.trim().to_string()),
+            None => (value.trim().to_string(), value.trim().to_string()),
+        })
+        .collect()
+}
+
+// Unlock the vault and return the environment variables of the declared secrets
+pub fn get_secret_envs(
+    config: &Config,
+    declared: &[(String, String)],
+) -> Result<Vec<(String, String)>, String> {
+    if declared.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let vault = open_vault(config, false)?;
+    declared
+        .iter()
+        .map(|(var, name)| match vault.secrets.get(name) {
+            Some(value) => Ok((var.clone(), value.clone())),
+            None => Err(format!("secret '{}' doesn't exist", name)),
+        })
+        .collect()
+}
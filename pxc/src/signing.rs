@@ -0,0 +1,310 @@
+use std::convert::TryInto;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use super::error::Error;
+use super::meta::{self, Meta};
+use super::remotes::sha256_hex;
+use super::secrets::{from_hex, to_hex, write_private};
+use super::{get_pxc_path, read_lines, Config, MapEntry};
+
+// keys/signing: the hex secret key of pxc sign, created on first use
+// keys/trusted: one line per trusted public key
+//   <name>;<hex public key>
+// A signed command has the line signature;<hex public key>;<hex signature> in its
+// metadata. The signature covers the name and the SHA-256 checksums of the script and of
+// the metadata that changes how the command runs (see signed_meta).
+const OWN_KEY: &str = "self";
+
+// Metadata keys that don't change how a command runs, the catalog keys are written by
+// remote accept from the checked index
+const UNSIGNED_KEYS: [&str; 5] = ["signature", "description", "version", "sha256", "remote"];
+
+pub enum Status {
+    // Signed by the key with this name in the trust list
+    Trusted(String),
+    // Signed by the key with this hex public key, which isn't trusted
+    Untrusted(String),
+    // The signature doesn't match the script
+    Tampered,
+    Unsigned,
+}
+
+impl Status {
+    pub fn describe(&self) -> String {
+        match self {
+            Status::Trusted(name) => format!("signed by '{}'", name),
+            Status::Untrusted(key) => format!("signed by the untrusted key {}", key),
+            Status::Tampered => "signature doesn't match the script or metadata".to_string(),
+            Status::Unsigned => "unsigned".to_string(),
+        }
+    }
+}
+
+fn get_keys_path() -> PathBuf {
+    Path::new(&get_pxc_path()).join("keys")
+}
+
+// The signed metadata lines in a canonical form: sorted key;value lines
+fn signed_meta(entry_meta: &Meta) -> String {
+    let mut lines: Vec<String> = entry_meta
+        .get_entries()
+        .into_iter()
+        .filter(|(key, _)| !UNSIGNED_KEYS.contains(key))
+        .map(|(key, value)| format!("{};{}\n", key, value))
+        .collect();
+    lines.sort();
+    lines.concat()
+}
+
+fn message(entry: &MapEntry, script: &[u8], entry_meta: &Meta) -> Vec<u8> {
+    format!(
+        "pxc signature v2\n{}\n{}\n{}\n",
+        entry.name,
+        sha256_hex(script),
+        sha256_hex(signed_meta(entry_meta).as_bytes())
+    )
+    .into_bytes()
+}
+
+fn read_key(create: bool) -> Result<Option<SigningKey>, Error> {
+    let key_path = get_keys_path().join("signing");
+    if !key_path.exists() {
+        if !create {
+            return Ok(None);
+        }
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        write_private(&key_path, to_hex(&secret).as_bytes())
+            .map_err(|e| Error::io(format!("unable to create '{}'", key_path.display()), e))?;
+        println!(
+            "[sign] created the signing key '{}', public key {}",
+            key_path.display(),
+            to_hex(SigningKey::from_bytes(&secret).verifying_key().as_bytes())
+        );
+    }
+
+    let hex = fs::read_to_string(&key_path)
+        .map_err(|e| Error::io(format!("unable to read '{}'", key_path.display()), e))?;
+    let secret: [u8; 32] = from_hex(hex.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::Store(format!("'{}' isn't a signing key", key_path.display())))?;
+    Ok(Some(SigningKey::from_bytes(&secret)))
+}
+
+// The trust list, with the own public key first
+pub fn read_trusted() -> Vec<(String, String)> {
+    let mut trusted = Vec::new();
+    if let Ok(Some(key)) = read_key(false) {
+        trusted.push((OWN_KEY.to_string(), to_hex(key.verifying_key().as_bytes())));
+    }
+    if let Ok(lines) = read_lines(get_keys_path().join("trusted")) {
+        for line in lines.map_while(Result::ok) {
+            if let Some((name, key)) = line.split_once(';') {
+                trusted.push((name.to_string(), key.to_ascii_lowercase()));
+            }
+        }
+    }
+    trusted
+}
+
+fn save_trusted(trusted: &[(String, String)]) -> Result<(), Error> {
+    let trusted_path = get_keys_path().join("trusted");
+    let content: String = trusted
+        .iter()
+        .filter(|(name, _)| name != OWN_KEY)
+        .map(|(name, key)| format!("{};{}\n", name, key))
+        .collect();
+    fs::create_dir_all(get_keys_path())
+        .and_then(|_| fs::write(&trusted_path, content))
+        .map_err(|e| Error::io(format!("unable to write '{}'", trusted_path.display()), e))
+}
+
+pub fn check(entry: &MapEntry) -> Status {
+    let entry_meta = meta::read_meta(entry);
+    let (key, signature) = match entry_meta
+        .get("signature")
+        .and_then(|value| value.split_once(';'))
+    {
+        Some(signature) => signature,
+        None => return Status::Unsigned,
+    };
+    let key = key.to_ascii_lowercase();
+
+    let verifying_key = from_hex(&key)
+        .and_then(|bytes| bytes.try_into().ok())
+        .and_then(|bytes: [u8; 32]| VerifyingKey::from_bytes(&bytes).ok());
+    let signature = from_hex(signature)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(|bytes: [u8; 64]| Signature::from_bytes(&bytes));
+    let script = fs::read(entry.cmd_path()).unwrap_or_default();
+    let valid = match (verifying_key, signature) {
+        (Some(verifying_key), Some(signature)) => verifying_key
+            .verify_strict(&message(entry, &script, &entry_meta), &signature)
+            .is_ok(),
+        _ => false,
+    };
+    if !valid {
+        return Status::Tampered;
+    }
+
+    match read_trusted().into_iter().find(|(_, other)| *other == key) {
+        Some((name, _)) => Status::Trusted(name),
+        None => Status::Untrusted(key),
+    }
+}
+
+pub fn sign(entry: &MapEntry) -> Result<(), Error> {
+    let key = read_key(true)?.ok_or_else(|| Error::Store("no signing key".to_string()))?;
+    let cmd_path = entry.cmd_path();
+    let script = fs::read(&cmd_path)
+        .map_err(|e| Error::io(format!("unable to read '{}'", cmd_path.display()), e))?;
+    let mut entry_meta = meta::read_meta(entry);
+    let signature = key.sign(&message(entry, &script, &entry_meta));
+    entry_meta.set(
+        "signature",
+        &format!(
+            "{};{}",
+            to_hex(key.verifying_key().as_bytes()),
+            to_hex(&signature.to_bytes())
+        ),
+    );
+    meta::save_meta(entry, &entry_meta)?;
+    println!("[sign] signed '{}'", entry.name);
+    Ok(())
+}
+
+pub fn trust(name: &str, key: &str) -> Result<(), Error> {
+    let key = key.to_ascii_lowercase();
+    let valid = from_hex(&key)
+        .and_then(|bytes| bytes.try_into().ok())
+        .is_some_and(|bytes: [u8; 32]| VerifyingKey::from_bytes(&bytes).is_ok());
+    if !valid {
+        return Err(Error::Usage(format!(
+            "'{}' isn't an ed25519 public key",
+            key
+        )));
+    }
+    if name.is_empty() || name.contains(';') || name == OWN_KEY {
+        return Err(Error::Usage(format!("invalid key name '{}'", name)));
+    }
+
+    let mut trusted = read_trusted();
+    if let Some((other, _)) = trusted
+        .iter()
+        .find(|(other, k)| *other == name || *k == key)
+    {
+        return Err(Error::Usage(format!(
+            "the key '{}' is trusted already",
+            other
+        )));
+    }
+    trusted.push((name.to_string(), key));
+    save_trusted(&trusted)?;
+    println!("[trust] trusting the key '{}'", name);
+    Ok(())
+}
+
+pub fn untrust(name: &str) -> Result<(), Error> {
+    let mut trusted = read_trusted();
+    if name == OWN_KEY || !trusted.iter().any(|(other, _)| other == name) {
+        return Err(Error::NotFound(format!(
+            "no trusted key with name '{}'",
+            name
+        )));
+    }
+    trusted.retain(|(other, _)| other != name);
+    save_trusted(&trusted)?;
+    println!("[trust] removed the key '{}'", name);
+    Ok(())
+}
+
+pub fn list_trusted() {
+    let trusted = read_trusted();
+    if trusted.is_empty() {
+        println!("[trust] no trusted keys, add one with 'pxc trust add <name> <key>'");
+        return;
+    }
+    println!("{: <16}KEY", "NAME");
+    println!("{}", "🭶".repeat(80));
+    for (name, key) in trusted {
+        println!("{: <16}{}", name, key);
+    }
+}
+
+// off, warn or refuse for the commands of a layer, personal commands aren't checked
+fn get_policy<'a>(entry: &MapEntry, config: &'a Config) -> &'a str {
+    if entry.is_personal() {
+        return "off";
+    }
+    config
+        .layer_verify
+        .get(&entry.layer)
+        .unwrap_or(&config.verify)
+}
+
+// Commands of shared layers and remotes need a valid signature of a trusted key
+pub fn check_run(entry: &MapEntry, config: &Config) -> Result<(), Error> {
+    let policy = get_policy(entry, config);
+    if policy == "off" {
+        return Ok(());
+    }
+    let status = check(entry);
+    if let Status::Trusted(_) = status {
+        return Ok(());
+    }
+    let problem = format!(
+        "'{}' of layer '{}': {}",
+        entry.name,
+        entry.layer,
+        status.describe()
+    );
+    if policy == "refuse" {
+        return Err(Error::Exec(format!(
+            "{}, refusing to run it (see 'pxc verify')",
+            problem
+        )));
+    }
+    eprintln!("[verify] warning: {}", problem);
+    Ok(())
+}
+
+// The signature status of all commands. Fails if a signature doesn't match or a command
+// wouldn't run because of its layer's policy.
+pub fn verify(entries: &[MapEntry], config: &Config) -> Result<(), Error> {
+    println!("{: <28}{: <12}STATUS", "NAME", "LAYER");
+    println!("{}", "🭶".repeat(80));
+    let mut tampered = 0;
+    let mut refused = 0;
+    for entry in entries {
+        let status = check(entry);
+        match status {
+            Status::Tampered => tampered += 1,
+            Status::Trusted(_) => {}
+            _ if get_policy(entry, config) == "refuse" => refused += 1,
+            _ => {}
+        }
+        println!(
+            "{: <28}{: <12}{}",
+            entry.name,
+            entry.layer,
+            status.describe()
+        );
+    }
+
+    if tampered + refused > 0 {
+        return Err(Error::Store(format!(
+            "{} commands don't match their signature, {} more would be refused",
+            tampered, refused
+        )));
+    }
+    println!(
+        "[verify] {} commands checked, no problems found",
+        entries.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::error::Error;
+use super::{get_pxc_path, run_editor, Config};
+
+// Built-in templates, a file with the same name in templates/ takes precedence
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("sh", "#!/bin/sh\n# {{name}} ({{category}}), author: {{author}}\n\n"),
+    (
+        "bash",
+        "#!/usr/bin/env bash\n# {{name}} ({{category}}), author: {{author}}\nset -euo pipefail\n\n",
+    ),
+    (
+        "python",
+        "#!/usr/bin/env python3\n# {{name}} ({{category}}), author: {{author}}\nimport sys\n\n\ndef main(args):\n    pass\n\n\nif __name__ == \"__main__\":\n    main(sys.argv[1:])\n",
+    ),
+    (
+        "node",
+        "#!/usr/bin/env node\n// {{name}} ({{category}}), author: {{author}}\n'use strict';\n\nconst args = process.argv.slice(2);\n",
+    ),
+];
+
+// Template name that disables the category and global default templates
+const NO_TEMPLATE: &str = "none";
+
+fn get_templates_dir() -> PathBuf {
+    Path::new(&get_pxc_path()).join("templates")
+}
+
+fn get_builtin_template(template_name: &str) -> Option<&'static str> {
+    BUILTIN_TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == template_name)
+        .map(|(_, template)| *template)
+}
+
+fn get_template(template_name: &str) -> Option<String> {
+    match fs::read_to_string(get_templates_dir().join(template_name)) {
+        Ok(template) => Some(template),
+        Err(_) => get_builtin_template(template_name).map(|template| template.to_string()),
+    }
+}
+
+// Pick the template for a new command: the explicitly requested one, otherwise the
+// default of the category, otherwise the global default. Returns the name of a
+// template that doesn't exist as error.
+pub fn resolve_template(
+    config: &Config,
+    template_name: Option<&str>,
+    category: &str,
+) -> Result<Option<(String, String)>, String> {
+    let template_name = match template_name
+        .or_else(|| config.category_templates.get(category).map(|t| t.as_str()))
+    {
+        Some(template_name) => template_name,
+        None if !config.template.is_empty() => &config.template,
+        None => return Ok(None),
+    };
+
+    if template_name == NO_TEMPLATE {
+        return Ok(None);
+    }
+
+    match get_template(template_name) {
+        Some(template) => Ok(Some((template_name.to_string(), template))),
+        None => Err(template_name.to_string()),
+    }
+}
+
+// Replace the {{name}}, {{category}} and {{author}} placeholders
+pub fn render(template: &str, name: &str, category: &str, author: &str) -> String {
+    template
+        .replace("{{name}}", name)
+        .replace("{{category}}", category)
+        .replace("{{author}}", author)
+}
+
+pub fn list_templates(config: &Config) {
+    let mut user_templates: Vec<String> = match fs::read_dir(get_templates_dir()) {
+        Ok(dir) => dir
+            .filter_map(|file| file.ok())
+            .filter_map(|file| file.file_name().into_string().ok())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    user_templates.sort();
+
+    println!("TEMPLATE\tSOURCE\t\tDEFAULT FOR");
+    println!("{}", "🭶".repeat(41));
+
+    let mut names: Vec<(&str, &str)> = Vec::new();
+    for (name, _) in BUILTIN_TEMPLATES {
+        if user_templates.iter().any(|t| t == name) {
+            names.push((name, "user"));
+        } else {
+            names.push((name, "built-in"));
+        }
+    }
+    for name in user_templates.iter() {
+        if get_builtin_template(name).is_none() {
+            names.push((name, "user"));
+        }
+    }
+
+    for (name, source) in names {
+        let mut default_for: Vec<&str> = config
+            .category_templates
+            .iter()
+            .filter(|(_, template)| *template == name)
+            .map(|(category, _)| category.as_str())
+            .collect();
+        default_for.sort();
+        if config.template == name {
+            default_for.insert(0, "*");
+        }
+        println!("{: <16}{: <16}{}", name, source, default_for.join(","));
+    }
+}
+
+// Open the user template in the editor, a new user template starts from the
+// built-in template with the same name
+pub fn edit_template(config: &Config, template_name: &str) -> Result<(), Error> {
+    let templates_dir = get_templates_dir();
+    fs::create_dir_all(&templates_dir)
+        .map_err(|e| Error::io(format!("unable to create '{}'", templates_dir.display()), e))?;
+
+    let template_path = templates_dir.join(template_name);
+    if !template_path.exists() {
+        let template = get_builtin_template(template_name).unwrap_or("");
+        fs::write(&template_path, template)
+            .map_err(|e| Error::io(format!("unable to create '{}'", template_path.display()), e))?;
+    }
+
+    println!("[template] editing template '{}'", template_name);
+    run_editor(config, &template_path)
+}
@@ -0,0 +1,257 @@
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{confirm, matches_pattern, meta};
+use super::{Config, MapEntry};
+
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+// Time a run gets to exit after SIGTERM before it is killed
+const KILL_AFTER: Duration = Duration::from_secs(2);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Match a relative path against a glob, * and ? stay within a path component,
+// ** matches any number of components
+fn matches_glob(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            matches_glob(&pattern[1..], path)
+                || (!path.is_empty() && matches_glob(pattern, &path[1..]))
+        }
+        (Some(part), Some(name)) => {
+            matches_pattern(part, name) && matches_glob(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+// Hidden files and directories (.git) only count if the pattern names them
+fn is_watched(patterns: &[String], path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty()).collect();
+    patterns.iter().any(|pattern| {
+        let pattern_parts: Vec<&str> = pattern.split('/').filter(|p| !p.is_empty()).collect();
+        let hidden = parts.iter().any(|part| part.starts_with('.'));
+        // Absolute patterns match the absolute paths of their watches, relative ones the
+        // paths below the current directory
+        pattern.starts_with('/') == path.starts_with('/')
+            && (!hidden || pattern.starts_with('.') || pattern.contains("/."))
+            && matches_glob(&pattern_parts, &parts)
+    })
+}
+
+// The directory to watch for a pattern, the components before the first wildcard
+fn get_base_dir(pattern: &str) -> PathBuf {
+    let mut base = PathBuf::from(if pattern.starts_with('/') { "/" } else { "." });
+    for part in pattern.split('/').filter(|p| !p.is_empty()) {
+        if part.contains(['*', '?']) {
+            break;
+        }
+        base.push(part);
+    }
+    // A pattern naming a file watches its directory
+    if base.is_file() {
+        base.pop();
+    }
+    base
+}
+
+fn add_watches(
+    inotify: &mut Inotify,
+    dir: &Path,
+    dirs: &mut HashMap<WatchDescriptor, PathBuf>,
+) -> io::Result<()> {
+    if dirs.values().any(|watched| watched == dir) {
+        return Ok(());
+    }
+    let mask = WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO;
+    let wd = inotify.watches().add(dir, mask)?;
+    dirs.insert(wd, dir.to_path_buf());
+
+    for sub in fs::read_dir(dir)?.map_while(Result::ok) {
+        let name = sub.file_name();
+        // Nothing below hidden directories like .git is watched
+        if sub.file_type().is_ok_and(|t| t.is_dir()) && !name.to_string_lossy().starts_with('.') {
+            add_watches(inotify, &sub.path(), dirs)?;
+        }
+    }
+    Ok(())
+}
+
+fn start_run(entry: &MapEntry, args: &[String]) -> Option<Child> {
+    if io::stdout().is_terminal() {
+        // Clear the screen and move the cursor to the top
+        print!("\x1b[2J\x1b[H");
+        let _ = io::stdout().flush();
+    }
+
+    let mut command = match env::current_exe() {
+        Ok(exe) => Command::new(exe),
+        Err(_) => Command::new("pxc"),
+    };
+    // Confirmed once when the watch started. A process group of its own so
+    // stopping a run also stops everything it started.
+    command
+        .arg("--yes")
+        .arg(&entry.name)
+        .args(args)
+        .process_group(0);
+    match command.spawn() {
+        Ok(child) => Some(child),
+        Err(e) => {
+            eprintln!("[watch] failed to run '{}': {}", entry.name, e);
+            None
+        }
+    }
+}
+
+fn stop_run(child: &mut Child) {
+    if let Ok(Some(_)) = child.try_wait() {
+        return;
+    }
+
+    let group = -(child.id() as libc::pid_t);
+    unsafe {
+        libc::kill(group, libc::SIGTERM);
+    }
+    let deadline = Instant::now() + KILL_AFTER;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    unsafe {
+        libc::kill(group, libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+// Run the command and run it again whenever a watched file changes, until Ctrl-C
+pub fn watch(
+    entry: &MapEntry,
+    patterns: &[String],
+    debounce: Duration,
+    args: &[String],
+    config: &Config,
+) {
+    if !confirm::confirm_run(entry, &meta::read_meta(entry), config.assume_yes) {
+        return;
+    }
+
+    let mut inotify = match Inotify::init() {
+        Ok(inotify) => inotify,
+        Err(e) => {
+            eprintln!("[watch] unable to use inotify: {}", e);
+            return;
+        }
+    };
+
+    let mut dirs: HashMap<WatchDescriptor, PathBuf> = HashMap::new();
+    for pattern in patterns {
+        let base = get_base_dir(pattern);
+        if let Err(e) = add_watches(&mut inotify, &base, &mut dirs) {
+            eprintln!("[watch] unable to watch '{}': {}", base.display(), e);
+            return;
+        }
+    }
+
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as *const () as libc::sighandler_t,
+        );
+    }
+
+    let mut child = start_run(entry, args);
+    let mut reported = false;
+    let mut changed_at: Option<Instant> = None;
+    let mut buffer = [0; 4096];
+
+    while !INTERRUPTED.load(Ordering::SeqCst) {
+        let mut new_dirs: Vec<PathBuf> = Vec::new();
+        match inotify.read_events(&mut buffer) {
+            Ok(events) => {
+                for event in events {
+                    let (dir, name) = match (dirs.get(&event.wd), event.name) {
+                        (Some(dir), Some(name)) => (dir, name),
+                        _ => continue,
+                    };
+                    let path = dir.join(name);
+                    if event.mask.contains(EventMask::ISDIR) {
+                        if event
+                            .mask
+                            .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                        {
+                            new_dirs.push(path);
+                        }
+                        continue;
+                    }
+                    if is_watched(patterns, path.strip_prefix(".").unwrap_or(&path)) {
+                        changed_at = Some(Instant::now());
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => {
+                eprintln!("[watch] failed to read events: {}", e);
+                break;
+            }
+        }
+        for dir in new_dirs {
+            if !dir
+                .file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+            {
+                let _ = add_watches(&mut inotify, &dir, &mut dirs);
+            }
+        }
+
+        // Wait until the files stopped changing for the debounce time
+        if changed_at.is_some_and(|at| at.elapsed() >= debounce) {
+            changed_at = None;
+            if let Some(child) = child.as_mut() {
+                stop_run(child);
+            }
+            child = start_run(entry, args);
+            reported = false;
+        }
+
+        if !reported {
+            if let Some(Ok(Some(status))) = child.as_mut().map(|c| c.try_wait()) {
+                println!(
+                    "[watch] '{}' exited with {}, waiting for changes",
+                    entry.name,
+                    status
+                        .code()
+                        .map_or("a signal".to_string(), |c| c.to_string())
+                );
+                reported = true;
+            }
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    if let Some(child) = child.as_mut() {
+        stop_run(child);
+    }
+}
@@ -0,0 +1,111 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use super::envs::{expand, lookup_var, EnvVar};
+use super::meta::Meta;
+use super::MapEntry;
+
+// Where a command runs, set with workdir;<policy> in the metadata
+pub enum WorkDir {
+    Caller,
+    Fixed(String),
+    GitRoot,
+    Script,
+    Temp,
+}
+
+pub fn get_workdir(meta: &Meta) -> WorkDir {
+    match meta.get("workdir") {
+        None | Some("") | Some("caller") => WorkDir::Caller,
+        Some("git-root") => WorkDir::GitRoot,
+        Some("script") => WorkDir::Script,
+        Some("temp") => WorkDir::Temp,
+        Some(path) => WorkDir::Fixed(path.to_string()),
+    }
+}
+
+pub fn format_workdir(workdir: &WorkDir) -> String {
+    match workdir {
+        WorkDir::Caller => "caller".to_string(),
+        WorkDir::Fixed(path) => format!("fixed {}", path),
+        WorkDir::GitRoot => "git-root".to_string(),
+        WorkDir::Script => "script".to_string(),
+        WorkDir::Temp => "temp".to_string(),
+    }
+}
+
+fn get_git_root() -> Result<PathBuf, String> {
+    let output = Command::new("git")
+        .arg("rev-parse")
+        .arg("--show-toplevel")
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err("the current directory is not inside a git repository".to_string());
+    }
+
+    Ok(PathBuf::from(
+        String::from_utf8_lossy(&output.stdout).trim_end(),
+    ))
+}
+
+// Resolve the policy to a directory. Returns the directory to run in (None for
+// the caller's cwd) and whether it is a temporary directory the caller has to
+// remove with remove_temp after the run.
+pub fn prepare(
+    workdir: &WorkDir,
+    entry: &MapEntry,
+    cmdpath: &str,
+    command_env: &[EnvVar],
+) -> Result<(Option<PathBuf>, bool), String> {
+    match workdir {
+        WorkDir::Caller => Ok((None, false)),
+        WorkDir::GitRoot => Ok((Some(get_git_root()?), false)),
+        WorkDir::Script => match Path::new(cmdpath).parent() {
+            Some(dir) => Ok((Some(dir.to_path_buf()), false)),
+            None => Err(format!("script '{}' has no parent directory", cmdpath)),
+        },
+        WorkDir::Fixed(path) => {
+            let path = expand(path, &|name| lookup_var(command_env, name));
+            let path = match path.strip_prefix("~/") {
+                Some(rest) => match home::home_dir() {
+                    Some(home) => home.join(rest),
+                    None => PathBuf::from(&path),
+                },
+                None => PathBuf::from(&path),
+            };
+            if !path.is_dir() {
+                return Err(format!(
+                    "working directory '{}' doesn't exist",
+                    path.display()
+                ));
+            }
+            Ok((Some(path), false))
+        }
+        WorkDir::Temp => {
+            let path =
+                env::temp_dir().join(format!("pxc-run-{}-{}", entry.filehash, std::process::id()));
+            fs::create_dir(&path).map_err(|e| {
+                format!(
+                    "unable to create temporary directory '{}': {}",
+                    path.display(),
+                    e
+                )
+            })?;
+            Ok((Some(path), true))
+        }
+    }
+}
+
+pub fn remove_temp(path: &Path) {
+    if let Err(e) = fs::remove_dir_all(path) {
+        eprintln!(
+            "[workdir] failed to remove temporary directory '{}': {}",
+            path.display(),
+            e
+        );
+    }
+}